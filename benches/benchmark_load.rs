@@ -7,6 +7,7 @@ use futures::FutureExt;
 use std::sync::Arc;
 
 use async_filemanager::AsyncFileManager;
+use async_filemanager::FileLoadFuture;
 use async_filemanager::LoadStatus;
 use std::{convert::TryFrom, path::PathBuf};
 #[derive(Debug, Eq, PartialEq)]
@@ -59,6 +60,25 @@ async fn load_async(f: &[&str]) {
     }
     black_box(vec);
 }
+/// Loads every file with its own `FileLoadFuture`, read with io_uring if `io_uring` is set.
+async fn load_futures(f: &[&str], pool: &Arc<futures::executor::ThreadPool>, io_uring: bool) {
+    let mut fut = FuturesUnordered::new();
+    for file in f.iter() {
+        let mut path = PathBuf::from("benches/benchfiles/");
+        path.push(file);
+        let l = FileLoadFuture::<LoadedFile>::new(path, pool.clone());
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        let l = if io_uring { l.with_io_uring() } else { l };
+        #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+        let _ = io_uring;
+        fut.push(l);
+    }
+    let mut vec = Vec::new();
+    while let Some(val) = fut.next().await {
+        vec.push(val.unwrap());
+    }
+    black_box(vec);
+}
 fn load_sync(f: &[&str]) {
     let mut vec = Vec::new();
     for file in f.iter() {
@@ -113,5 +133,35 @@ fn criterion_benchmark(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, criterion_benchmark);
+/// The pool against io_uring reading every file in `benches/benchfiles`, run with
+/// `cargo bench --features io-uring` to include io_uring.
+fn read_backends(c: &mut Criterion) {
+    let files = [
+        "s01", "s02", "s03", "s04", "s05", "s06", "s07", "s08", "s09", "s10", "s11", "s12", "s13",
+        "s14", "s15", "s16",
+    ];
+    let pool = Arc::new(ThreadPoolBuilder::new().pool_size(8).create().unwrap());
+    let mut group = c.benchmark_group("read backend");
+    group.bench_function("pool", |b| {
+        b.iter(|| {
+            async_std::task::block_on(async { load_futures(black_box(&files), &pool, false).await })
+        })
+    });
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    {
+        if async_filemanager::UringReader::shared().is_none() {
+            eprintln!("io_uring is unavailable, its loads read on the pool");
+        }
+        group.bench_function("io_uring", |b| {
+            b.iter(|| {
+                async_std::task::block_on(async {
+                    load_futures(black_box(&files), &pool, true).await
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark, read_backends);
 criterion_main!(benches);
@@ -0,0 +1,398 @@
+use crate::{
+    configmanager::ConfigManagerError, AsyncFileManager, FileLoadFuture, Identifier, LoadStatus,
+    NodeInfo, NodeStatus, SharedGraph, Source, StreamDecode,
+};
+use futures::executor::ThreadPool;
+use futures::{future::BoxFuture, FutureExt, TryFutureExt};
+use serde::{Deserialize, Deserializer};
+use std::{
+    any::{type_name, Any, TypeId},
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+thread_local! {
+    static COLLECTED: RefCell<Option<Vec<Arc<dyn Dependency>>>> = RefCell::new(None);
+}
+
+/// Runs `decode` and returns every `AssetRef` deserialized by it.
+pub(crate) fn collect_dependencies<R>(decode: impl FnOnce() -> R) -> (R, Vec<Arc<dyn Dependency>>) {
+    COLLECTED.with(|collected| collected.replace(Some(Vec::new())));
+    let result = decode();
+    let dependencies = COLLECTED.with(|collected| collected.replace(None).unwrap_or_default());
+    (result, dependencies)
+}
+
+/// Type-erased access to the `AsyncFileManager`s of a `Registry`.
+trait AnyManager: Send {
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn invalidate(&mut self, path: &Path) -> bool;
+    fn schedule(&mut self, path: &Path);
+    fn ids(&self) -> Vec<Identifier>;
+    fn node_info(&self, id: &Identifier) -> NodeInfo;
+    fn set_graph(&mut self, graph: SharedGraph);
+}
+
+impl<L> AnyManager for AsyncFileManager<L>
+where
+    L: StreamDecode + Send + Sync + Unpin + 'static,
+{
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+    fn invalidate(&mut self, path: &Path) -> bool {
+        AsyncFileManager::invalidate(self, path)
+    }
+    fn schedule(&mut self, path: &Path) {
+        AsyncFileManager::schedule(self, path)
+    }
+    fn ids(&self) -> Vec<Identifier> {
+        AsyncFileManager::ids(self)
+    }
+    fn node_info(&self, id: &Identifier) -> NodeInfo {
+        AsyncFileManager::node_info(self, id)
+    }
+    fn set_graph(&mut self, graph: SharedGraph) {
+        AsyncFileManager::set_graph(self, graph)
+    }
+}
+
+/// File managers of every asset type loaded through a `ConfigManager`, keyed by asset type.
+///
+/// It is handed to `Dependency::load`, only the crate itself calls into it.
+///
+/// The managers are only locked while they are called, never across an `.await`.
+/// `AsyncFileManager::load` and `get` only poll their futures once and never suspend,
+/// so they are run to completion in place. All managers share the graph of the registry,
+/// which is locked after them if both are.
+pub struct Registry {
+    pool: Arc<ThreadPool>,
+    source: Arc<dyn Source>,
+    managers: Mutex<HashMap<TypeId, Box<dyn AnyManager>>>,
+    graph: Mutex<SharedGraph>,
+}
+
+impl Registry {
+    /// Managers of the registry read their files from `source`.
+    pub(crate) fn new(pool: Arc<ThreadPool>, source: Arc<dyn Source>) -> Self {
+        Self {
+            pool,
+            source,
+            managers: Mutex::new(HashMap::new()),
+            graph: Mutex::new(SharedGraph::default()),
+        }
+    }
+    pub(crate) fn pool(&self) -> &ThreadPool {
+        &self.pool
+    }
+    pub(crate) fn source(&self) -> &Arc<dyn Source> {
+        &self.source
+    }
+    pub(crate) fn graph(&self) -> SharedGraph {
+        self.graph.lock().unwrap().clone()
+    }
+    /// Records relations in `graph` from now on, in every manager, taking along those so far.
+    pub(crate) fn set_graph(&self, graph: SharedGraph) {
+        let mut managers = self.managers.lock().unwrap();
+        let old = std::mem::replace(&mut *self.graph.lock().unwrap(), graph.clone());
+        if !Arc::ptr_eq(&old, &graph) {
+            let old = old.lock().unwrap();
+            let mut graph = graph.lock().unwrap();
+            for (parent, child) in old.edges() {
+                graph.add(parent.clone(), child.clone());
+            }
+        }
+        for manager in managers.values_mut() {
+            manager.set_graph(graph.clone());
+        }
+    }
+    /// Invalidates the file and all loaded files depending on it, in every manager.
+    /// With `reschedule` they are read again on their next `get`, along with files depending
+    /// on it in other managers sharing the graph.
+    pub(crate) fn invalidate(&self, path: &Path, reschedule: bool) -> Vec<PathBuf> {
+        let id = Identifier::from(path.to_owned());
+        let graph = self.graph();
+        let dependants = if reschedule {
+            graph.lock().unwrap().touch(&id)
+        } else {
+            graph.lock().unwrap().transitive_dependants(&id)
+        };
+        let affected = std::iter::once(id)
+            .chain(dependants)
+            .filter_map(|id| match id {
+                Identifier::Path(path) => Some(path),
+                Identifier::Index(_) => None,
+            })
+            .collect::<Vec<_>>();
+        let mut managers = self.managers.lock().unwrap();
+        let mut invalidated = Vec::new();
+        for path in affected {
+            let mut present = false;
+            for manager in managers.values_mut() {
+                if manager.invalidate(&path) {
+                    present = true;
+                    if reschedule {
+                        manager.schedule(&path);
+                    }
+                }
+            }
+            if present {
+                // Relations are recorded again when the file is decoded and resolved.
+                graph
+                    .lock()
+                    .unwrap()
+                    .clear_dependencies(&path.clone().into());
+                invalidated.push(path);
+            }
+        }
+        invalidated
+    }
+    /// Renders every file known to any manager and their relations as Graphviz DOT.
+    pub(crate) fn to_dot(&self) -> String {
+        let managers = self.managers.lock().unwrap();
+        let ids = managers.values().flat_map(|manager| manager.ids());
+        let graph = self.graph();
+        let graph = graph.lock().unwrap();
+        graph.to_dot(ids, |id| {
+            // A file is only ever loaded by the manager of its type.
+            managers
+                .values()
+                .map(|manager| manager.node_info(id))
+                .find(|info| info.status != NodeStatus::NotLoading)
+                .unwrap_or_default()
+        })
+    }
+    /// Records that `parent` uses `child`.
+    pub(crate) fn add_dependency(&self, parent: &Path, child: &Path) {
+        self.graph()
+            .lock()
+            .unwrap()
+            .add(parent.to_owned().into(), child.to_owned().into());
+    }
+    /// Loads the file through the manager of its type, registering the type if needed.
+    pub(crate) async fn fetch<L>(&self, path: &Path) -> Result<Arc<L>, Arc<Error>>
+    where
+        L: StreamDecode + Send + Sync + Unpin + 'static,
+    {
+        let status = self
+            .with_manager::<L, _>(true, |manager| {
+                load_now(manager, path);
+                get_now(manager, path)
+            })
+            .expect("Registering a manager can not fail!");
+        match status {
+            LoadStatus::Loaded(asset) => Ok(asset),
+            LoadStatus::Loading(f) => f.await,
+            LoadStatus::Error(e) => Err(e),
+            LoadStatus::NotLoading => Err(Arc::new(Error::new(
+                ErrorKind::Other,
+                format!("{} could not be scheduled", path.display()),
+            ))),
+        }
+    }
+    pub(crate) fn contains<L: Any>(&self) -> bool {
+        self.managers
+            .lock()
+            .unwrap()
+            .contains_key(&TypeId::of::<L>())
+    }
+    pub(crate) fn register<L>(&self)
+    where
+        L: StreamDecode + Send + Sync + Unpin + 'static,
+    {
+        self.managers
+            .lock()
+            .unwrap()
+            .entry(TypeId::of::<L>())
+            .or_insert_with(|| {
+                let mut manager =
+                    AsyncFileManager::<L>::with_source(self.pool.clone(), self.source.clone());
+                manager.set_graph(self.graph());
+                Box::new(manager)
+            });
+    }
+    /// Calls `f` with the manager of `L` locked, it must not `.await` or call back into the registry.
+    pub(crate) fn with_manager<L, R>(
+        &self,
+        auto_register: bool,
+        f: impl FnOnce(&mut AsyncFileManager<L>) -> R,
+    ) -> Result<R, ConfigManagerError>
+    where
+        L: StreamDecode + Send + Sync + Unpin + 'static,
+    {
+        if !self.contains::<L>() {
+            if !auto_register {
+                return Err(ConfigManagerError::NotRegistered(type_name::<L>()));
+            }
+            self.register::<L>();
+        }
+        let mut managers = self.managers.lock().unwrap();
+        let manager = managers
+            .get_mut(&TypeId::of::<L>())
+            .and_then(|manager| manager.as_any_mut().downcast_mut::<AsyncFileManager<L>>())
+            .unwrap();
+        Ok(f(manager))
+    }
+}
+
+/// Runs a manager's `load` in place, it only polls the new load once and never suspends.
+pub(crate) fn load_now<L>(manager: &mut AsyncFileManager<L>, path: &Path)
+where
+    L: StreamDecode + Send + Unpin + 'static,
+{
+    let _ = manager.load(path).now_or_never();
+}
+
+/// Runs a manager's `get` in place. It never suspends, if it did the file counts as loading.
+pub(crate) fn get_now<L>(
+    manager: &mut AsyncFileManager<L>,
+    path: &Path,
+) -> LoadStatus<L, FileLoadFuture<L>>
+where
+    L: StreamDecode + Send + Unpin + 'static,
+{
+    let status = manager.get(path).now_or_never();
+    match status {
+        Some(status) => status,
+        None => match manager.loading(path) {
+            Some(f) => LoadStatus::Loading(f),
+            None => LoadStatus::NotLoading,
+        },
+    }
+}
+
+/// A reference to another asset found while decoding.
+pub trait Dependency: Send + Sync {
+    fn path(&self) -> &Path;
+    /// Loads the asset and its own dependencies through the registry.
+    fn load(
+        self: Arc<Self>,
+        registry: Arc<Registry>,
+        chain: Vec<PathBuf>,
+    ) -> BoxFuture<'static, Result<(), Arc<Error>>>;
+}
+
+/// Assets that can reference other assets by `AssetRef`.
+pub trait Dependencies {
+    fn dependencies(&self) -> Vec<Arc<dyn Dependency>> {
+        Vec::new()
+    }
+}
+
+/// Loads all dependencies of `asset`, skipping references back into `chain`.
+pub(crate) async fn load_dependencies<D: Dependencies + Sync + ?Sized>(
+    asset: &D,
+    registry: Arc<Registry>,
+    chain: Vec<PathBuf>,
+) -> Result<(), Arc<Error>> {
+    let loads = asset
+        .dependencies()
+        .into_iter()
+        .filter(|dependency| !chain.iter().any(|path| path == dependency.path()))
+        .map(|dependency| {
+            let path = dependency.path().to_owned();
+            dependency
+                .load(registry.clone(), chain.clone())
+                .map_err(move |e| {
+                    Arc::new(Error::new(
+                        e.kind(),
+                        format!("dependency {} failed: {}", path.display(), e),
+                    ))
+                })
+        });
+    futures::future::try_join_all(loads).await?;
+    Ok(())
+}
+
+struct Reference<L> {
+    path: PathBuf,
+    asset: Mutex<Option<Arc<L>>>,
+}
+
+impl<L> Dependency for Reference<L>
+where
+    L: StreamDecode + Dependencies + Send + Sync + Unpin + 'static,
+{
+    fn path(&self) -> &Path {
+        &self.path
+    }
+    fn load(
+        self: Arc<Self>,
+        registry: Arc<Registry>,
+        mut chain: Vec<PathBuf>,
+    ) -> BoxFuture<'static, Result<(), Arc<Error>>> {
+        async move {
+            let asset = registry.fetch::<L>(&self.path).await?;
+            if let Some(parent) = chain.last() {
+                registry.add_dependency(parent, &self.path);
+            }
+            chain.push(self.path.clone());
+            load_dependencies(&*asset, registry, chain).await?;
+            *self.asset.lock().unwrap() = Some(asset);
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// A path to another asset inside a RON file, deserialized from a string.
+///
+/// Decoding the parent through a `ConfigManager` schedules the referenced asset,
+/// the parent only counts as loaded once all of its references are.
+pub struct AssetRef<L> {
+    reference: Arc<Reference<L>>,
+}
+
+impl<L> AssetRef<L> {
+    pub fn path(&self) -> &Path {
+        &self.reference.path
+    }
+    /// The referenced asset, `None` until it is loaded.
+    pub fn get(&self) -> Option<Arc<L>> {
+        self.reference.asset.lock().unwrap().clone()
+    }
+}
+
+impl<L> Clone for AssetRef<L> {
+    fn clone(&self) -> Self {
+        Self {
+            reference: self.reference.clone(),
+        }
+    }
+}
+
+impl<L> fmt::Debug for AssetRef<L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AssetRef")
+            .field(&self.reference.path)
+            .finish()
+    }
+}
+
+impl<L> PartialEq for AssetRef<L> {
+    fn eq(&self, other: &Self) -> bool {
+        self.reference.path == other.reference.path
+    }
+}
+
+impl<'de, L> Deserialize<'de> for AssetRef<L>
+where
+    L: StreamDecode + Dependencies + Send + Sync + Unpin + 'static,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let reference = Arc::new(Reference {
+            path: PathBuf::deserialize(deserializer)?,
+            asset: Mutex::new(None),
+        });
+        COLLECTED.with(|collected| {
+            if let Some(collected) = collected.borrow_mut().as_mut() {
+                collected.push(reference.clone());
+            }
+        });
+        Ok(AssetRef { reference })
+    }
+}
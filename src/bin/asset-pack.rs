@@ -0,0 +1,224 @@
+use async_filemanager::{Compression, Pack, PackEntry, PackWriter};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Component, Path},
+    process::exit,
+};
+
+const USAGE: &str = "\
+Usage:
+    asset-pack pack <dir> <pack> [--store] [--alignment <bytes>]
+    asset-pack list <pack>
+    asset-pack extract <pack> <dir> [<path>...]
+    asset-pack verify <pack>
+    asset-pack diff <old pack> <new pack>
+
+pack     packs every file below <dir>, deflated unless --store is given
+list     prints size, stored size, compression, CRC-32 and path of every file
+extract  writes the given files or all files below <dir>
+verify   reads every file and checks its checksum
+diff     prints files only in the old (-) or new (+) pack or changed between them (~)
+
+verify and diff exit with 1 if they find corrupt or changed files.";
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match run(&args) {
+        Ok(true) => {}
+        Ok(false) => exit(1),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            exit(2);
+        }
+    }
+}
+
+/// Runs the subcommand in `args`, returns whether no problems or differences were found.
+fn run(args: &[String]) -> Result<bool, String> {
+    let (command, args) = args.split_first().ok_or(USAGE)?;
+    match (command.as_str(), args) {
+        ("pack", [dir, pack, options @ ..]) => pack_dir(dir, pack, options),
+        ("list", [pack]) => list(pack),
+        ("extract", [pack, dir, paths @ ..]) => extract(pack, dir, paths),
+        ("verify", [pack]) => verify(pack),
+        ("diff", [old, new]) => diff(old, new),
+        _ => Err(USAGE.to_owned()),
+    }
+}
+
+fn open(path: &str) -> Result<Pack, String> {
+    Pack::open(path).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Entries of the pack by path, for output in a stable order.
+fn by_path(pack: &Pack) -> BTreeMap<&Path, &PackEntry> {
+    pack.entries()
+        .iter()
+        .map(|entry| (entry.path.as_path(), entry))
+        .collect()
+}
+
+fn pack_dir(dir: &str, pack: &str, options: &[String]) -> Result<bool, String> {
+    let mut compression = Compression::Deflate;
+    let mut writer = PackWriter::new();
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "--store" => compression = Compression::None,
+            "--alignment" => {
+                let alignment = options
+                    .next()
+                    .and_then(|alignment| alignment.parse::<u32>().ok())
+                    .filter(|alignment| alignment.is_power_of_two())
+                    .ok_or("--alignment needs a power of two")?;
+                writer = writer.with_alignment(alignment);
+            }
+            _ => return Err(format!("unknown option {}\n\n{}", option, USAGE)),
+        }
+    }
+    writer
+        .add_dir(dir, compression)
+        .map_err(|e| format!("{}: {}", dir, e))?;
+    writer.write(pack).map_err(|e| format!("{}: {}", pack, e))?;
+    println!("packed {} into {}", dir, pack);
+    Ok(true)
+}
+
+fn list(pack: &str) -> Result<bool, String> {
+    let pack = open(pack)?;
+    for (path, entry) in by_path(&pack) {
+        println!(
+            "{:>10} {:>10} {:<7} {:08x} {}",
+            entry.size,
+            entry.stored_size,
+            format!("{:?}", entry.compression).to_lowercase(),
+            entry.checksum,
+            path.display()
+        );
+    }
+    Ok(true)
+}
+
+fn extract(pack: &str, dir: &str, paths: &[String]) -> Result<bool, String> {
+    let pack = open(pack)?;
+    let entries = if paths.is_empty() {
+        by_path(&pack).into_values().collect()
+    } else {
+        paths
+            .iter()
+            .map(|path| {
+                pack.entry(path)
+                    .ok_or_else(|| format!("{} not found in {}", path, pack.path().display()))
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    for entry in entries {
+        // Paths are relative in packs written by `PackWriter`, others are refused.
+        if !entry
+            .path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(format!("{}: not a relative path", entry.path.display()));
+        }
+        let target = Path::new(dir).join(&entry.path);
+        let bytes = pack
+            .read_entry(entry)
+            .map_err(|e| format!("{}: {}", entry.path.display(), e))?;
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("{}: {}", parent.display(), e))?;
+        }
+        fs::write(&target, bytes).map_err(|e| format!("{}: {}", target.display(), e))?;
+    }
+    Ok(true)
+}
+
+fn verify(pack: &str) -> Result<bool, String> {
+    let pack = open(pack)?;
+    let mut corrupt = 0;
+    for (path, entry) in by_path(&pack) {
+        if let Err(e) = pack.read_entry(entry) {
+            println!("{}: {}", path.display(), e);
+            corrupt += 1;
+        }
+    }
+    println!("{} of {} files corrupt", corrupt, pack.entries().len());
+    Ok(corrupt == 0)
+}
+
+fn diff(old: &str, new: &str) -> Result<bool, String> {
+    let (old, new) = (open(old)?, open(new)?);
+    let (old, new) = (by_path(&old), by_path(&new));
+    let mut paths = old.keys().chain(new.keys()).collect::<Vec<_>>();
+    paths.sort();
+    paths.dedup();
+    let mut same = true;
+    for path in paths {
+        let change = match (old.get(path), new.get(path)) {
+            (Some(_), None) => "-",
+            (None, Some(_)) => "+",
+            (Some(old), Some(new)) if (old.size, old.checksum) != (new.size, new.checksum) => "~",
+            _ => continue,
+        };
+        println!("{} {}", change, path.display());
+        same = false;
+    }
+    Ok(same)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run;
+    use std::fs;
+
+    fn run_args(args: &[&str]) -> Result<bool, String> {
+        run(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn commands() {
+        let dir = std::env::temp_dir().join(format!(
+            "async_filemanager_asset_pack_{}",
+            std::process::id()
+        ));
+        // Left over by a run that failed, extracting must start from an empty directory.
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let old = dir.join("old.pack");
+        let new = dir.join("new.pack");
+        let extracted = dir.join("extracted");
+        let (old, new, extracted) = (
+            old.to_str().unwrap(),
+            new.to_str().unwrap(),
+            extracted.to_str().unwrap(),
+        );
+
+        assert_eq!(run_args(&["pack", "testfiles", old]), Ok(true));
+        assert_eq!(
+            run_args(&["pack", "testfiles", new, "--store", "--alignment", "4096"]),
+            Ok(true)
+        );
+        assert!(run_args(&["pack", "testfiles", new, "--alignment", "3"]).is_err());
+        assert_eq!(run_args(&["list", old]), Ok(true));
+        assert_eq!(run_args(&["verify", new]), Ok(true));
+        // The same files, stored differently.
+        assert_eq!(run_args(&["diff", old, new]), Ok(true));
+
+        assert_eq!(
+            run_args(&["extract", old, extracted, "material.ron"]),
+            Ok(true)
+        );
+        assert_eq!(
+            fs::read(dir.join("extracted/material.ron")).unwrap(),
+            fs::read("testfiles/material.ron").unwrap()
+        );
+        assert!(run_args(&["extract", old, extracted, "missing.ron"]).is_err());
+        assert_eq!(run_args(&["pack", extracted, new]), Ok(true));
+        assert_eq!(run_args(&["diff", old, new]), Ok(false));
+
+        assert!(run_args(&["unknown"]).is_err());
+        assert!(run_args(&[]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
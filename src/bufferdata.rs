@@ -0,0 +1,61 @@
+use crate::{gpuasset::GpuAsset, staging::div_ceil};
+use wgpu::{Buffer, BufferUsage, Device, Queue};
+
+#[derive(Debug, PartialEq)]
+pub struct BufferData {
+    name: Option<String>,
+    usage: BufferUsage,
+    raw: Vec<u8>,
+}
+
+impl BufferData {
+    /// Buffer contents are padded with zeros to `wgpu::COPY_BUFFER_ALIGNMENT`.
+    pub fn new(name: Option<String>, usage: BufferUsage, mut raw: Vec<u8>) -> Self {
+        let align = wgpu::COPY_BUFFER_ALIGNMENT;
+        let padded = div_ceil(raw.len() as u64, align) * align;
+        raw.resize(padded as usize, 0);
+        Self { name, usage, raw }
+    }
+    pub fn vertex(name: Option<String>, raw: Vec<u8>) -> Self {
+        Self::new(name, BufferUsage::VERTEX, raw)
+    }
+    pub fn index(name: Option<String>, raw: Vec<u8>) -> Self {
+        Self::new(name, BufferUsage::INDEX, raw)
+    }
+    pub fn uniform(name: Option<String>, raw: Vec<u8>) -> Self {
+        Self::new(name, BufferUsage::UNIFORM, raw)
+    }
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+}
+
+impl GpuAsset for BufferData {
+    type Resource = Buffer;
+    fn gpu_size(&self) -> u64 {
+        self.raw.len() as u64
+    }
+    fn upload(&self, device: &Device, queue: &Queue) -> Buffer {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: self.name.as_deref(),
+            size: self.raw.len() as u64,
+            usage: self.usage | BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&buffer, 0, &self.raw);
+        buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BufferData;
+
+    #[test]
+    fn padding() {
+        let data = BufferData::vertex(None, vec![1, 2, 3, 4, 5]);
+        assert_eq!(data.raw(), &[1, 2, 3, 4, 5, 0, 0, 0]);
+        let data = BufferData::uniform(None, vec![1, 2, 3, 4]);
+        assert_eq!(data.raw(), &[1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,1329 @@
+use crate::{
+    assetref::{
+        collect_dependencies, get_now, load_dependencies, load_now, Dependencies, Dependency,
+        Registry,
+    },
+    validation::{Problem, Schema, ValidationReport},
+    AsyncFileManager, ChunkReader, DependencyGraph, FileSystem, Format, FormatError, LoadStatus,
+    SharedGraph, Source, StreamDecode,
+};
+use futures::executor::ThreadPool;
+use futures::{
+    future::{BoxFuture, Shared},
+    task::SpawnExt,
+    Future, FutureExt, TryFutureExt,
+};
+use ron::{Map, Value};
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::{Path, PathBuf};
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::HashMap,
+    fmt, fs,
+    io::{Error, ErrorKind, Write},
+    ops::Deref,
+    pin::Pin,
+    sync::{Arc, MutexGuard, RwLock},
+    task::Poll,
+    time::SystemTime,
+};
+
+/// Types that can be decoded from RON and the other config formats,
+/// implemented for everything serde can deserialize.
+pub trait Config: Sized {
+    fn decode(format: Format, bytes: &[u8]) -> Result<Self, FormatError>;
+    /// Decodes a RON document that was merged with the files it extends.
+    fn from_ron_value(value: Value) -> Result<Self, FormatError>;
+}
+
+impl<T: DeserializeOwned> Config for T {
+    fn decode(format: Format, bytes: &[u8]) -> Result<Self, FormatError> {
+        format.decode(bytes)
+    }
+    fn from_ron_value(value: Value) -> Result<Self, FormatError> {
+        value.into_rust().map_err(FormatError::from)
+    }
+}
+
+/// Name of the field naming the file a RON document extends.
+const EXTENDS: &str = "extends";
+
+/// An untyped RON document and the base file it extends, if any.
+#[derive(Debug)]
+pub(crate) struct RonDocument {
+    value: Value,
+    base: Option<PathBuf>,
+}
+
+impl RonDocument {
+    /// Takes the `extends` field out of the parsed document. Like every path in a config
+    /// file, the base is relative to the root of the source, see `ConfigManager`.
+    fn new(path: &Path, mut value: Value) -> Result<Self, Error> {
+        let base = match &mut value {
+            Value::Map(map) => map.remove(&Value::String(EXTENDS.to_owned())),
+            _ => None,
+        };
+        let base = match base {
+            Some(Value::String(base)) => Some(PathBuf::from(base)),
+            Some(_) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}: `{}` must be a path", path.display(), EXTENDS),
+                ))
+            }
+            None => None,
+        };
+        Ok(RonDocument { value, base })
+    }
+    fn parse(path: &Path, bytes: &[u8]) -> Result<Self, Error> {
+        let value = Format::Ron
+            .decode::<Value>(bytes)
+            .map_err(|e| decode_error(path, e))?;
+        RonDocument::new(path, value)
+    }
+    fn extends(&self) -> bool {
+        self.base.is_some()
+    }
+}
+
+impl StreamDecode for RonDocument {
+    fn decode(path: PathBuf, reader: &mut ChunkReader) -> Result<Self, Error> {
+        let bytes = reader.read_to_vec()?;
+        RonDocument::parse(&path, &bytes)
+    }
+}
+
+/// Merges `layer` into `base`, structs and maps field by field, everything else is replaced.
+fn merge(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (Value::Map(base), Value::Map(layer)) => merge_map(base, layer),
+        (base, layer) => *base = layer,
+    }
+}
+
+fn merge_map(base: &mut Map, mut layer: Map) {
+    // `Map` has neither `get_mut` nor an owning iterator, so values are moved out by key.
+    let keys = layer.keys().cloned().collect::<Vec<_>>();
+    for key in keys {
+        let value = layer.remove(&key).unwrap();
+        let merged = match base.remove(&key) {
+            Some(mut old) => {
+                merge(&mut old, value);
+                old
+            }
+            None => value,
+        };
+        base.insert(key, merged);
+    }
+}
+
+/// Prefixes the error with the include chain that led to it.
+fn include_error(chain: &[PathBuf], kind: ErrorKind, e: impl fmt::Display) -> Arc<Error> {
+    let chain = chain
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>();
+    Arc::new(Error::new(
+        kind,
+        format!("include chain {}: {}", chain.join(" -> "), e),
+    ))
+}
+
+/// Loads the bases of `document` through the registry and merges it on top of them.
+async fn resolve_bases(
+    registry: &Registry,
+    path: &Path,
+    document: &RonDocument,
+) -> Result<Value, Arc<Error>> {
+    let mut chain = vec![path.to_owned()];
+    let mut layers = vec![document.value.clone()];
+    let mut next = document.base.clone();
+    while let Some(base) = next {
+        let cycle = chain.contains(&base);
+        registry.add_dependency(chain.last().unwrap(), &base);
+        chain.push(base);
+        if cycle {
+            return Err(include_error(&chain, ErrorKind::InvalidData, "cycle"));
+        }
+        let document = registry
+            .fetch::<RonDocument>(chain.last().unwrap())
+            .await
+            .map_err(|e| include_error(&chain, e.kind(), e))?;
+        layers.push(document.value.clone());
+        next = document.base.clone();
+    }
+    let mut merged = layers.pop().unwrap();
+    while let Some(layer) = layers.pop() {
+        merge(&mut merged, layer);
+    }
+    Ok(merged)
+}
+
+/// A value decoded from a config file on the worker pool.
+pub struct ConfigFile<T> {
+    value: T,
+    dependencies: Vec<Arc<dyn Dependency>>,
+}
+
+impl<T> Deref for ConfigFile<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ConfigFile<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConfigFile")
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+impl<T: PartialEq> PartialEq for ConfigFile<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<T> Dependencies for ConfigFile<T> {
+    fn dependencies(&self) -> Vec<Arc<dyn Dependency>> {
+        self.dependencies.clone()
+    }
+}
+
+impl<T: Config> ConfigFile<T> {
+    fn decode(decode: impl FnOnce() -> Result<T, FormatError>) -> Result<Self, FormatError> {
+        let (value, dependencies) = collect_dependencies(decode);
+        value.map(|value| ConfigFile {
+            value,
+            dependencies,
+        })
+    }
+}
+
+/// A config file of type `T` as read on the worker pool.
+pub(crate) enum Decoded<T> {
+    File(Arc<ConfigFile<T>>),
+    /// The RON file extends a base, it is decoded once the bases are merged in.
+    Extends(RonDocument),
+}
+
+impl<T: Config> Decoded<T> {
+    fn decode(
+        path: PathBuf,
+        bytes: Vec<u8>,
+        format: Format,
+        schema: Option<(&Schema, &dyn Source)>,
+    ) -> Result<Self, Error> {
+        // RON documents are parsed once, to look for `extends` and decode `T` from, others
+        // only if they are checked. Without either `T` is decoded straight from the bytes.
+        let document = if format == Format::Ron || schema.is_some() {
+            let document = format
+                .decode::<Value>(&bytes)
+                .map_err(|e| decode_error(&path, e))?;
+            Some(document)
+        } else {
+            None
+        };
+        let document = match (format, document) {
+            (Format::Ron, Some(document)) => {
+                let document = RonDocument::new(&path, document)?;
+                if document.extends() {
+                    return Ok(Decoded::Extends(document));
+                }
+                Some(document.value)
+            }
+            (_, document) => document,
+        };
+        let mut problems = match (schema, &document) {
+            (Some((schema, source)), Some(document)) => {
+                schema.check(document, std::str::from_utf8(&bytes).ok(), source)
+            }
+            _ => Vec::new(),
+        };
+        let decoded = match document {
+            Some(document) => ConfigFile::decode(|| T::from_ron_value(document)),
+            None => ConfigFile::decode(|| T::decode(format, &bytes)),
+        };
+        match decoded {
+            Ok(file) if problems.is_empty() => Ok(Decoded::File(Arc::new(file))),
+            Err(e) if problems.is_empty() => Err(decode_error(&path, e)),
+            Ok(_) => Err(validation_error(path, problems)),
+            Err(e) => {
+                problems.push(e.into());
+                Err(validation_error(path, problems))
+            }
+        }
+    }
+}
+
+impl<T: Config> StreamDecode for Decoded<T> {
+    fn decode(path: PathBuf, reader: &mut ChunkReader) -> Result<Self, Error> {
+        let bytes = reader.read_to_vec()?;
+        let format = Format::from_path(&path).map_err(|e| decode_error(&path, e))?;
+        Decoded::decode(path, bytes, format, None)
+    }
+}
+
+/// Prefixes the error with the file and, for syntax errors, the line and column.
+fn decode_error(path: &Path, e: FormatError) -> Error {
+    let message = if e.line == 0 {
+        format!("{}: {}", path.display(), e)
+    } else {
+        format!("{}:{}", path.display(), e)
+    };
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn validation_error(path: PathBuf, problems: Vec<Problem>) -> Error {
+    Error::new(ErrorKind::InvalidData, ValidationReport { path, problems })
+}
+
+/// Errors of the typed `ConfigManager` registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigManagerError {
+    NotRegistered(&'static str),
+    AlreadyRegistered(&'static str),
+    UnknownExtension(PathBuf),
+    ExtensionRegistered(String),
+}
+
+impl fmt::Display for ConfigManagerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigManagerError::NotRegistered(name) => {
+                write!(f, "Config type [{}] not registered!", name)
+            }
+            ConfigManagerError::AlreadyRegistered(name) => {
+                write!(f, "Config type [{}] already registered!", name)
+            }
+            ConfigManagerError::UnknownExtension(path) => {
+                write!(f, "No config type registered for [{}]!", path.display())
+            }
+            ConfigManagerError::ExtensionRegistered(extension) => {
+                write!(f, "Extension [{}] already registered!", extension)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigManagerError {}
+
+/// Resolves once the config file and every asset it references are loaded.
+pub struct ConfigLoadFuture<T> {
+    inner: BoxFuture<'static, Result<Arc<ConfigFile<T>>, Arc<Error>>>,
+}
+
+impl<T> Future for ConfigLoadFuture<T> {
+    type Output = Result<Arc<ConfigFile<T>>, Arc<Error>>;
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        Pin::new(&mut self.inner).poll(cx)
+    }
+}
+
+/// A config file whose type was picked by its extension, see `ConfigManager::get_any`.
+#[derive(Clone)]
+pub struct AnyConfig {
+    path: PathBuf,
+    type_name: &'static str,
+    file: Arc<dyn Any + Send + Sync>,
+}
+
+impl AnyConfig {
+    fn new<T: Any + Send + Sync>(path: PathBuf, file: Arc<ConfigFile<T>>) -> Self {
+        Self {
+            path,
+            type_name: type_name::<T>(),
+            file,
+        }
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+    pub fn is<T: Any>(&self) -> bool {
+        self.file.is::<ConfigFile<T>>()
+    }
+    /// The file as `T`, `None` if it is of another type.
+    pub fn downcast<T: Any + Send + Sync>(&self) -> Option<Arc<ConfigFile<T>>> {
+        self.file.clone().downcast::<ConfigFile<T>>().ok()
+    }
+}
+
+impl fmt::Debug for AnyConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AnyConfig")
+            .field("path", &self.path)
+            .field("type_name", &self.type_name)
+            .finish()
+    }
+}
+
+/// Resolves once a config file of any registered extension is loaded.
+pub struct AnyConfigLoadFuture {
+    inner: BoxFuture<'static, Result<Arc<AnyConfig>, Arc<Error>>>,
+}
+
+impl Future for AnyConfigLoadFuture {
+    type Output = Result<Arc<AnyConfig>, Arc<Error>>;
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        Pin::new(&mut self.inner).poll(cx)
+    }
+}
+
+type AnyConfigStatus = LoadStatus<AnyConfig, AnyConfigLoadFuture>;
+
+/// `load` and `get` of the type registered for an extension.
+struct Extension {
+    load: for<'a> fn(&'a mut ConfigManager, PathBuf) -> BoxFuture<'a, ()>,
+    get: for<'a> fn(&'a mut ConfigManager, PathBuf) -> BoxFuture<'a, AnyConfigStatus>,
+}
+
+impl Extension {
+    fn new<T: Any + Config + Send + Sync + Unpin>() -> Self {
+        Self {
+            load: |manager, path| {
+                async move {
+                    manager
+                        .load::<T, _>(path)
+                        .await
+                        .expect("Extensions are only added for registered types!")
+                }
+                .boxed()
+            },
+            get: |manager, path| {
+                async move {
+                    match manager
+                        .get::<T, _>(&path)
+                        .await
+                        .expect("Extensions are only added for registered types!")
+                    {
+                        LoadStatus::NotLoading => LoadStatus::NotLoading,
+                        LoadStatus::Loaded(file) => {
+                            LoadStatus::Loaded(Arc::new(AnyConfig::new(path, file)))
+                        }
+                        LoadStatus::Error(e) => LoadStatus::Error(e),
+                        LoadStatus::Loading(f) => LoadStatus::Loading(
+                            AnyConfigLoadFuture {
+                                inner: f
+                                    .map_ok(move |file| Arc::new(AnyConfig::new(path, file)))
+                                    .boxed(),
+                            }
+                            .shared(),
+                        ),
+                    }
+                }
+                .boxed()
+            },
+        }
+    }
+}
+
+/// Typed registry of config files, decoded by extension as RON, JSON, TOML or YAML.
+///
+/// Paths inside config files, of the base in `extends`, of `AssetRef`s and those checked by
+/// `Schema::file_exists`, are relative to the root of the source, not to the file naming them.
+pub struct ConfigManager {
+    registry: Arc<Registry>,
+    graph: SharedGraph,
+    auto_register: bool,
+    extensions: HashMap<String, Extension>,
+    formats: Arc<RwLock<HashMap<String, Format>>>,
+    schemas: Arc<RwLock<HashMap<TypeId, Arc<Schema>>>>,
+    /// Modification time and size of every file as `save` left it.
+    saved: HashMap<PathBuf, (SystemTime, u64)>,
+}
+
+/// The entry of the longest extension `path` ends in.
+fn match_extension<'a, E>(
+    path: &Path,
+    extensions: impl Iterator<Item = (&'a String, E)>,
+) -> Option<(&'a String, E)> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    extensions
+        .filter(|(extension, _)| {
+            name.len() > extension.len()
+                && name.ends_with(extension.as_str())
+                && name[..name.len() - extension.len()].ends_with('.')
+        })
+        .max_by_key(|(extension, _)| extension.len())
+}
+
+/// The format registered for the extension of `path`, else the one of its last extension.
+fn format_of(formats: &HashMap<String, Format>, path: &Path) -> Result<Format, Error> {
+    match match_extension(path, formats.iter()) {
+        Some((_, format)) => Ok(*format),
+        None => Format::from_path(path).map_err(|e| decode_error(path, e)),
+    }
+}
+
+/// Replaces the file at `path` with `bytes` by renaming a temporary file next to it,
+/// so readers see either the old or the new file but never a partial write.
+fn write_atomic(path: &Path, bytes: &[u8]) -> Result<(), Error> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(".tmp");
+    let temp = path.with_file_name(temp_name);
+    let written = fs::File::create(&temp).and_then(|mut file| {
+        file.write_all(bytes)?;
+        file.sync_all()
+    });
+    match written.and_then(|_| fs::rename(&temp, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+/// Fails if the RON file at `path` extends a base, saving the merged value would drop
+/// `extends` and flatten the inheritance.
+fn check_not_extending(source: &dyn Source, path: &Path, format: Format) -> Result<(), Error> {
+    if format != Format::Ron {
+        return Ok(());
+    }
+    let bytes = match source.read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    match RonDocument::parse(path, &bytes) {
+        Ok(document) if document.extends() => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{}: extends a base and cannot be saved", path.display()),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Modification time and size, what `reload` compares to tell a write by `save` from others.
+fn file_version(source: &dyn Source, path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = source.metadata(path).ok()?;
+    Some((metadata.modified?, metadata.len))
+}
+
+impl ConfigManager {
+    pub fn new(pool: Arc<ThreadPool>) -> Self {
+        Self::with_source(pool, Arc::new(FileSystem::default()))
+    }
+    /// Reads config files and the assets they reference from `source`.
+    pub fn with_source(pool: Arc<ThreadPool>, source: Arc<dyn Source>) -> Self {
+        let registry = Arc::new(Registry::new(pool, source));
+        Self {
+            graph: registry.graph(),
+            registry,
+            auto_register: false,
+            extensions: HashMap::new(),
+            formats: Arc::new(RwLock::new(HashMap::new())),
+            schemas: Arc::new(RwLock::new(HashMap::new())),
+            saved: HashMap::new(),
+        }
+    }
+    /// If enabled, `load` and `get` register unknown types instead of returning an error.
+    pub fn set_auto_register(&mut self, auto_register: bool) {
+        self.auto_register = auto_register;
+    }
+    pub fn register_material<T: Any + Config + Send + Sync + Unpin>(
+        &mut self,
+    ) -> Result<(), ConfigManagerError> {
+        if self.registry.contains::<Decoded<T>>() {
+            return Err(ConfigManagerError::AlreadyRegistered(type_name::<T>()));
+        }
+        self.register::<T>();
+        Ok(())
+    }
+    /// Decodes files ending in `extension` as `format`, e.g. `"cfg"` as TOML.
+    /// Files with other extensions are decoded by their last extension and fail to load
+    /// if it is unknown.
+    pub fn register_format(
+        &mut self,
+        extension: &str,
+        format: Format,
+    ) -> Result<(), ConfigManagerError> {
+        let extension = extension.trim_start_matches('.').to_owned();
+        let mut formats = self.formats.write().unwrap();
+        if formats.contains_key(&extension) {
+            return Err(ConfigManagerError::ExtensionRegistered(extension));
+        }
+        formats.insert(extension, format);
+        Ok(())
+    }
+    /// Checks files of type `T` against `schema` when they are decoded, replacing an earlier one.
+    /// A file failing any check or decoding fails to load with a `ValidationReport`
+    /// of all problems as the inner error. Registers `T` if needed.
+    pub fn register_schema<T: Any + Config + Send + Sync + Unpin>(&mut self, schema: Schema) {
+        self.register::<T>();
+        self.schemas
+            .write()
+            .unwrap()
+            .insert(TypeId::of::<T>(), Arc::new(schema));
+    }
+    fn register<T: Any + Config + Send + Sync + Unpin>(&self) {
+        if self.registry.contains::<Decoded<T>>() {
+            return;
+        }
+        self.registry.register::<Decoded<T>>();
+        let formats = self.formats.clone();
+        let schemas = self.schemas.clone();
+        let source = self.registry.source().clone();
+        self.registry
+            .with_manager::<Decoded<T>, _>(false, |manager| {
+                manager.set_decoder(move |path, bytes| {
+                    let format = format_of(&formats.read().unwrap(), &path)?;
+                    let schema = schemas.read().unwrap().get(&TypeId::of::<T>()).cloned();
+                    let schema = schema.as_deref().map(|schema| (schema, &*source));
+                    Decoded::decode(path, bytes.into_vec(), format, schema)
+                })
+            })
+            .expect("Type was just registered!");
+    }
+    /// Lets `load_any` and `get_any` decode files ending in `extension` as `T`,
+    /// e.g. `"mat.ron"`. The longest matching extension wins. Registers `T` if needed.
+    pub fn register_extension<T: Any + Config + Send + Sync + Unpin>(
+        &mut self,
+        extension: &str,
+    ) -> Result<(), ConfigManagerError> {
+        let extension = extension.trim_start_matches('.').to_owned();
+        if self.extensions.contains_key(&extension) {
+            return Err(ConfigManagerError::ExtensionRegistered(extension));
+        }
+        self.register::<T>();
+        self.extensions.insert(extension, Extension::new::<T>());
+        Ok(())
+    }
+    fn extension(&self, path: &Path) -> Result<&Extension, ConfigManagerError> {
+        match_extension(path, self.extensions.iter())
+            .map(|(_, entry)| entry)
+            .ok_or_else(|| ConfigManagerError::UnknownExtension(path.to_owned()))
+    }
+    /// Like `load`, with the type picked by the extension of `path`.
+    pub async fn load_any<P: AsRef<Path>>(&mut self, path: P) -> Result<(), ConfigManagerError> {
+        let load = self.extension(path.as_ref())?.load;
+        load(self, path.as_ref().to_owned()).await;
+        Ok(())
+    }
+    /// Like `get`, with the type picked by the extension of `path`.
+    /// The returned `AnyConfig` is downcast to the registered type.
+    pub async fn get_any<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<LoadStatus<AnyConfig, AnyConfigLoadFuture>, ConfigManagerError> {
+        let get = self.extension(path.as_ref())?.get;
+        Ok(get(self, path.as_ref().to_owned()).await)
+    }
+    pub async fn load<T: Any + Config + Send + Sync + Unpin, P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<(), ConfigManagerError> {
+        self.with_manager::<T, _>(|manager| load_now(manager, path.as_ref()))
+    }
+    /// `Loaded` only once all `AssetRef`s inside the file are loaded as well,
+    /// a failed reference is reported as an error naming the dependency.
+    ///
+    /// A file with an `extends: "configs/base.ron"` field is deep-merged on top of its base,
+    /// which is loaded through this manager and may extend another file in turn.
+    pub async fn get<T: Any + Config + Send + Sync + Unpin, P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<LoadStatus<ConfigFile<T>, ConfigLoadFuture<T>>, ConfigManagerError> {
+        let parent = match self.with_manager::<T, _>(|manager| get_now(manager, path.as_ref()))? {
+            LoadStatus::Loaded(file) => futures::future::ok(file).boxed(),
+            LoadStatus::Loading(f) => f.boxed(),
+            LoadStatus::Error(e) => return Ok(LoadStatus::Error(e)),
+            LoadStatus::NotLoading => return Ok(LoadStatus::NotLoading),
+        };
+        let registry = self.registry.clone();
+        let schema = self
+            .schemas
+            .read()
+            .unwrap()
+            .get(&TypeId::of::<T>())
+            .cloned();
+        let path = path.as_ref().to_owned();
+        let mut f: Shared<ConfigLoadFuture<T>> = ConfigLoadFuture {
+            inner: async move {
+                let decoded = parent.await?;
+                let file = match &*decoded {
+                    Decoded::File(file) => file.clone(),
+                    Decoded::Extends(document) => {
+                        let merged = resolve_bases(&registry, &path, document).await?;
+                        // Lines of the merged document are unknown, problems are reported by field.
+                        if let Some(schema) = schema {
+                            let problems = schema.check(&merged, None, &**registry.source());
+                            if !problems.is_empty() {
+                                return Err(Arc::new(validation_error(path, problems)));
+                            }
+                        }
+                        let file = ConfigFile::decode(|| T::from_ron_value(merged))
+                            .map_err(|e| decode_error(&path, e))?;
+                        let file = Arc::new(file);
+                        // Cache the merged file so later gets skip merging.
+                        let merged = Arc::new(Decoded::File(file.clone()));
+                        registry
+                            .with_manager::<Decoded<T>, _>(true, |manager| {
+                                manager.replace(&path, &decoded, merged)
+                            })
+                            .expect("Registering a manager can not fail!");
+                        file
+                    }
+                };
+                let chain = vec![path];
+                load_dependencies(&*file, registry, chain).await?;
+                Ok(file)
+            }
+            .boxed(),
+        }
+        .shared();
+        Ok(match futures::poll!(&mut f) {
+            Poll::Ready(Ok(file)) => LoadStatus::Loaded(file),
+            Poll::Ready(Err(e)) => LoadStatus::Error(e),
+            Poll::Pending => LoadStatus::Loading(f),
+        })
+    }
+    /// Relations recorded between config files and the assets they reference.
+    pub fn graph(&self) -> MutexGuard<'_, DependencyGraph> {
+        self.graph.lock().unwrap()
+    }
+    /// Records relations in `graph` from now on, taking along those recorded so far.
+    /// Share it with the managers of the assets referenced by `AssetRef`s, e.g. textures,
+    /// to have files read again on their next `get` once such an asset is reloaded there.
+    /// See `AsyncFileManager::set_graph`.
+    pub fn set_graph(&mut self, graph: SharedGraph) {
+        self.registry.set_graph(graph.clone());
+        self.graph = graph;
+    }
+    pub fn shared_graph(&self) -> SharedGraph {
+        self.graph.clone()
+    }
+    /// Renders every loaded file and what it references as Graphviz DOT.
+    pub fn to_dot(&self) -> String {
+        self.registry.to_dot()
+    }
+    /// Writes `value` back to `path` in the format it is loaded in, pretty-printed with fields
+    /// in declaration order, and caches it as the loaded file. Files referencing or extending
+    /// it are read again on their next `get`. Registers `T` if needed.
+    ///
+    /// The value is validated like a loaded file and the file is replaced atomically on the
+    /// pool, a failed save leaves both the file and the cache untouched.
+    /// RON files extending a base are refused, as their inheritance would be flattened.
+    /// Only files the source reads from the local filesystem can be saved.
+    pub async fn save<T, P>(&mut self, path: P, value: &T) -> Result<(), Error>
+    where
+        T: Any + Config + Serialize + Send + Sync + Unpin,
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref().to_owned();
+        self.register::<T>();
+        let format = format_of(&self.formats.read().unwrap(), &path)?;
+        let bytes = format.encode(value).map_err(|e| decode_error(&path, e))?;
+        let schema = self
+            .schemas
+            .read()
+            .unwrap()
+            .get(&TypeId::of::<T>())
+            .cloned();
+        let source = self.registry.source().clone();
+        let target = source.local_path(&path).ok_or_else(|| {
+            Error::new(
+                ErrorKind::Unsupported,
+                format!("{}: the source can not be written to", path.display()),
+            )
+        })?;
+        let decode_path = path.clone();
+        let (decoded, version) = self
+            .registry
+            .pool()
+            .spawn_with_handle(async move {
+                check_not_extending(&*source, &decode_path, format)?;
+                // Decoding what is written caches exactly what a reload would,
+                // references included.
+                let decoded = Decoded::<T>::decode(
+                    decode_path.clone(),
+                    bytes.clone(),
+                    format,
+                    schema.as_deref().map(|schema| (schema, &*source)),
+                )?;
+                write_atomic(&target, &bytes)?;
+                Ok::<_, Error>((decoded, file_version(&*source, &decode_path)))
+            })
+            .expect("Error spawning save!")
+            .await?;
+        self.registry.invalidate(&path, true);
+        self.registry
+            .with_manager::<Decoded<T>, _>(false, |manager| {
+                manager.insert(&path, Arc::new(decoded))
+            })
+            .expect("Type was just registered!");
+        match version {
+            Some(version) => self.saved.insert(path, version),
+            None => self.saved.remove(&path),
+        };
+        Ok(())
+    }
+    /// Reads the file again, along with every loaded file referencing it.
+    /// Returns the reloaded paths, they are read on their next `get`.
+    ///
+    /// Nothing is reloaded while the file is unchanged since `save` wrote it,
+    /// so a file watcher calling this does not read back the manager's own writes.
+    pub fn reload<P: AsRef<Path>>(&mut self, path: P) -> Vec<PathBuf> {
+        if let Some(version) = self.saved.remove(path.as_ref()) {
+            if file_version(&**self.registry.source(), path.as_ref()) == Some(version) {
+                self.saved.insert(path.as_ref().to_owned(), version);
+                return Vec::new();
+            }
+        }
+        self.registry.invalidate(path.as_ref(), true)
+    }
+    /// Drops the file and every file referencing it. Returns the evicted paths.
+    pub fn evict<P: AsRef<Path>>(&mut self, path: P) -> Vec<PathBuf> {
+        self.registry.invalidate(path.as_ref(), false)
+    }
+    /// Calls `f` with the manager of `T` locked, see `Registry::with_manager`.
+    fn with_manager<T: Any + Config + Send + Sync + Unpin, R>(
+        &self,
+        f: impl FnOnce(&mut AsyncFileManager<Decoded<T>>) -> R,
+    ) -> Result<R, ConfigManagerError> {
+        if self.auto_register {
+            self.register::<T>();
+        }
+        self.registry
+            .with_manager::<Decoded<T>, _>(false, f)
+            .map_err(|_| ConfigManagerError::NotRegistered(type_name::<T>()))
+    }
+}
+
+/// Former name of `Config`, from when only RON was supported.
+#[deprecated(note = "renamed to `Config`")]
+pub trait Ron: Config {}
+
+#[allow(deprecated)]
+impl<T: Config> Ron for T {}
+
+#[deprecated(note = "renamed to `ConfigFile`")]
+pub type RonFile<T> = ConfigFile<T>;
+#[deprecated(note = "renamed to `ConfigLoadFuture`")]
+pub type RonLoadFuture<T> = ConfigLoadFuture<T>;
+#[deprecated(note = "renamed to `AnyConfig`")]
+pub type AnyRon = AnyConfig;
+#[deprecated(note = "renamed to `AnyConfigLoadFuture`")]
+pub type AnyRonLoadFuture = AnyConfigLoadFuture;
+#[deprecated(note = "renamed to `ConfigManager`")]
+pub type RonManager = ConfigManager;
+#[deprecated(note = "renamed to `ConfigManagerError`")]
+pub type RonManagerError = ConfigManagerError;
+
+#[cfg(test)]
+mod tests {
+    use super::{ConfigManager, ConfigManagerError};
+    use crate::{
+        AssetRef, AsyncFileManager, Format, ImageData, LoadStatus, MemorySource, Schema,
+        ValidationReport,
+    };
+    use futures::{executor::ThreadPool, task::SpawnExt};
+    use ron::Value;
+    use serde::{Deserialize, Serialize};
+    use std::{fs, io::ErrorKind, path::PathBuf, sync::Arc};
+
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+    struct Material {
+        name: String,
+        roughness: f32,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Surface {
+        roughness: f32,
+        metallic: f32,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct LayeredMaterial {
+        name: String,
+        surface: Surface,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Level {
+        name: String,
+        assets: Vec<PathBuf>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct TexturedMaterial {
+        name: String,
+        albedo: AssetRef<ImageData>,
+    }
+
+    #[test]
+    fn mattest() {
+        let pool = Arc::new(ThreadPool::new().unwrap());
+        let mut matman = ConfigManager::new(pool);
+        matman.register_material::<Material>().unwrap();
+        let path = PathBuf::new().join("testfiles/material.ron");
+        futures::executor::block_on(async {
+            matman.load::<Material, _>(&path).await.unwrap();
+
+            let material = match matman.get::<Material, _>(&path).await.unwrap() {
+                LoadStatus::Loaded(f) => f,
+                LoadStatus::Loading(f) => f.await.unwrap(),
+                _ => panic!(),
+            };
+            assert_eq!(
+                **material,
+                Material {
+                    name: String::from("stone"),
+                    roughness: 0.5
+                }
+            );
+        });
+    }
+
+    #[test]
+    fn syntax_error() {
+        let pool = Arc::new(ThreadPool::new().unwrap());
+        let mut matman = ConfigManager::new(pool);
+        matman.register_material::<Material>().unwrap();
+        let path = PathBuf::new().join("testfiles/broken.ron");
+        futures::executor::block_on(async {
+            matman.load::<Material, _>(&path).await.unwrap();
+
+            let error = match matman.get::<Material, _>(&path).await.unwrap() {
+                LoadStatus::Error(e) => e,
+                LoadStatus::Loading(f) => f.await.unwrap_err(),
+                _ => panic!(),
+            };
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+            assert!(error.to_string().starts_with("testfiles/broken.ron:4:"));
+        });
+    }
+
+    #[test]
+    fn registration() {
+        let pool = Arc::new(ThreadPool::new().unwrap());
+        let mut matman = ConfigManager::new(pool);
+        let path = PathBuf::new().join("testfiles/material.ron");
+        futures::executor::block_on(async {
+            assert!(matches!(
+                matman.load::<Material, _>(&path).await,
+                Err(ConfigManagerError::NotRegistered(_))
+            ));
+            matman.set_auto_register(true);
+            matman.load::<Material, _>(&path).await.unwrap();
+            assert!(matches!(
+                matman.register_material::<Material>(),
+                Err(ConfigManagerError::AlreadyRegistered(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn dependencies() {
+        let pool = Arc::new(ThreadPool::new().unwrap());
+        let mut matman = ConfigManager::new(pool);
+        matman.register_material::<TexturedMaterial>().unwrap();
+        let path = PathBuf::new().join("testfiles/textured.ron");
+        futures::executor::block_on(async {
+            matman.load::<TexturedMaterial, _>(&path).await.unwrap();
+
+            let material = match matman.get::<TexturedMaterial, _>(&path).await.unwrap() {
+                LoadStatus::Loaded(f) => f,
+                LoadStatus::Loading(f) => f.await.unwrap(),
+                _ => panic!(),
+            };
+            assert_eq!(material.name, "stone");
+            assert_eq!(material.albedo.path(), PathBuf::from("small_scream.png"));
+            assert!(material.albedo.get().is_some());
+        });
+    }
+    /// The manager moves to another thread, its futures are awaited on the pool.
+    #[test]
+    fn send() {
+        let pool = Arc::new(ThreadPool::new().unwrap());
+        let mut matman = ConfigManager::new(pool.clone());
+        matman.register_material::<TexturedMaterial>().unwrap();
+        let path = PathBuf::new().join("testfiles/textured.ron");
+        let material = std::thread::spawn(move || {
+            futures::executor::block_on(async {
+                matman.load::<TexturedMaterial, _>(&path).await.unwrap();
+                match matman.get::<TexturedMaterial, _>(&path).await.unwrap() {
+                    LoadStatus::Loaded(f) => f,
+                    LoadStatus::Loading(f) => pool.spawn_with_handle(f).unwrap().await.unwrap(),
+                    _ => panic!(),
+                }
+            })
+        })
+        .join()
+        .unwrap();
+        assert!(material.albedo.get().is_some());
+    }
+
+    #[test]
+    fn missing_dependency() {
+        let pool = Arc::new(ThreadPool::new().unwrap());
+        let mut matman = ConfigManager::new(pool);
+        matman.register_material::<TexturedMaterial>().unwrap();
+        let path = PathBuf::new().join("testfiles/missing_texture.ron");
+        futures::executor::block_on(async {
+            matman.load::<TexturedMaterial, _>(&path).await.unwrap();
+
+            let error = match matman.get::<TexturedMaterial, _>(&path).await.unwrap() {
+                LoadStatus::Error(e) => e,
+                LoadStatus::Loading(f) => f.await.unwrap_err(),
+                _ => panic!(),
+            };
+            assert_eq!(error.kind(), ErrorKind::NotFound);
+            assert!(error.to_string().contains("testfiles/missing.png"));
+        });
+    }
+
+    #[test]
+    fn reload() {
+        let pool = Arc::new(ThreadPool::new().unwrap());
+        let mut matman = ConfigManager::new(pool);
+        matman.register_material::<TexturedMaterial>().unwrap();
+        let path = PathBuf::new().join("testfiles/textured.ron");
+        let texture = PathBuf::new().join("small_scream.png");
+        futures::executor::block_on(async {
+            matman.load::<TexturedMaterial, _>(&path).await.unwrap();
+            if let LoadStatus::Loading(f) = matman.get::<TexturedMaterial, _>(&path).await.unwrap()
+            {
+                f.await.unwrap();
+            }
+            assert_eq!(
+                matman
+                    .graph()
+                    .dependants(&texture.clone().into())
+                    .collect::<Vec<_>>(),
+                vec![&path.clone().into()]
+            );
+
+            assert_eq!(matman.reload(&texture), vec![texture.clone(), path.clone()]);
+            let material = match matman.get::<TexturedMaterial, _>(&path).await.unwrap() {
+                LoadStatus::Loaded(f) => f,
+                LoadStatus::Loading(f) => f.await.unwrap(),
+                _ => panic!(),
+            };
+            assert!(material.albedo.get().is_some());
+            assert_eq!(matman.graph().dependants(&texture.into()).count(), 1);
+
+            assert_eq!(matman.evict(&path), vec![path.clone()]);
+            assert!(matches!(
+                matman.get::<TexturedMaterial, _>(&path).await,
+                Ok(LoadStatus::NotLoading)
+            ));
+        });
+    }
+
+    /// Reloading the texture in an image manager sharing the graph reaches the material.
+    #[test]
+    fn shared_graph() {
+        let pool = Arc::new(ThreadPool::new().unwrap());
+        let mut matman = ConfigManager::new(pool.clone());
+        matman.register_material::<TexturedMaterial>().unwrap();
+        let mut images = AsyncFileManager::<ImageData>::new(pool);
+        images.set_graph(matman.shared_graph());
+        let path = PathBuf::new().join("testfiles/textured.ron");
+        let texture = PathBuf::new().join("small_scream.png");
+        futures::executor::block_on(async {
+            matman.load::<TexturedMaterial, _>(&path).await.unwrap();
+            let material = match matman.get::<TexturedMaterial, _>(&path).await.unwrap() {
+                LoadStatus::Loaded(f) => f,
+                LoadStatus::Loading(f) => f.await.unwrap(),
+                _ => panic!(),
+            };
+            images.load(&texture).await;
+            assert_eq!(images.reload(&texture).await, vec![texture.clone()]);
+            let reloaded = match matman.get::<TexturedMaterial, _>(&path).await.unwrap() {
+                LoadStatus::Loading(f) => f.await.unwrap(),
+                _ => panic!("The material must be read again!"),
+            };
+            assert!(!Arc::ptr_eq(&material, &reloaded));
+            assert!(reloaded.albedo.get().is_some());
+        });
+    }
+
+    #[test]
+    fn extends() {
+        let pool = Arc::new(ThreadPool::new().unwrap());
+        let mut matman = ConfigManager::new(pool);
+        matman.register_material::<LayeredMaterial>().unwrap();
+        let path = PathBuf::new().join("testfiles/derived.ron");
+        let base = PathBuf::new().join("testfiles/base.ron");
+        futures::executor::block_on(async {
+            matman.load::<LayeredMaterial, _>(&path).await.unwrap();
+
+            let material = match matman.get::<LayeredMaterial, _>(&path).await.unwrap() {
+                LoadStatus::Loaded(f) => f,
+                LoadStatus::Loading(f) => f.await.unwrap(),
+                _ => panic!(),
+            };
+            assert_eq!(
+                **material,
+                LayeredMaterial {
+                    name: String::from("wet stone"),
+                    surface: Surface {
+                        roughness: 0.1,
+                        metallic: 0.0,
+                    },
+                }
+            );
+            assert_eq!(matman.reload(&base), vec![base.clone(), path.clone()]);
+        });
+    }
+
+    #[test]
+    fn extends_cycle() {
+        let pool = Arc::new(ThreadPool::new().unwrap());
+        let mut matman = ConfigManager::new(pool);
+        matman.register_material::<Material>().unwrap();
+        let path = PathBuf::new().join("testfiles/cycle_a.ron");
+        futures::executor::block_on(async {
+            matman.load::<Material, _>(&path).await.unwrap();
+
+            let error = match matman.get::<Material, _>(&path).await.unwrap() {
+                LoadStatus::Error(e) => e,
+                LoadStatus::Loading(f) => f.await.unwrap_err(),
+                _ => panic!(),
+            };
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+            assert_eq!(
+                error.to_string(),
+                "include chain testfiles/cycle_a.ron -> testfiles/cycle_b.ron \
+                 -> testfiles/cycle_a.ron: cycle"
+            );
+        });
+    }
+
+    #[test]
+    fn extensions() {
+        let pool = Arc::new(ThreadPool::new().unwrap());
+        let mut matman = ConfigManager::new(pool);
+        matman.register_extension::<Material>(".mat.ron").unwrap();
+        matman.register_extension::<LayeredMaterial>("ron").unwrap();
+        matman.register_extension::<Level>("level.ron").unwrap();
+        assert_eq!(
+            matman.register_extension::<Material>("mat.ron"),
+            Err(ConfigManagerError::ExtensionRegistered(String::from(
+                "mat.ron"
+            )))
+        );
+        let path = PathBuf::new().join("testfiles/cave.level.ron");
+        futures::executor::block_on(async {
+            matman.load_any(&path).await.unwrap();
+            let level = match matman.get_any(&path).await.unwrap() {
+                LoadStatus::Loaded(f) => f,
+                LoadStatus::Loading(f) => f.await.unwrap(),
+                _ => panic!(),
+            };
+            assert!(level.downcast::<Material>().is_none());
+            let level = level.downcast::<Level>().unwrap();
+            assert_eq!(level.name, "cave");
+
+            let mut names = Vec::new();
+            for asset in &level.assets {
+                matman.load_any(asset).await.unwrap();
+                let asset = match matman.get_any(asset).await.unwrap() {
+                    LoadStatus::Loaded(f) => f,
+                    LoadStatus::Loading(f) => f.await.unwrap(),
+                    _ => panic!(),
+                };
+                if let Some(material) = asset.downcast::<Material>() {
+                    names.push(material.name.clone());
+                } else if let Some(material) = asset.downcast::<LayeredMaterial>() {
+                    names.push(material.name.clone());
+                }
+            }
+            assert_eq!(names, vec!["stone", "wet stone"]);
+
+            assert!(matches!(
+                matman.load_any("small_scream.png").await,
+                Err(ConfigManagerError::UnknownExtension(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn formats() {
+        let pool = Arc::new(ThreadPool::new().unwrap());
+        let mut matman = ConfigManager::new(pool);
+        matman.register_material::<Material>().unwrap();
+        matman.register_format("cfg", Format::Toml).unwrap();
+        futures::executor::block_on(async {
+            for file in &[
+                "material.json",
+                "material.toml",
+                "material.yaml",
+                "material.cfg",
+            ] {
+                let path = PathBuf::new().join("testfiles").join(file);
+                matman.load::<Material, _>(&path).await.unwrap();
+                let material = match matman.get::<Material, _>(&path).await.unwrap() {
+                    LoadStatus::Loaded(f) => f,
+                    LoadStatus::Loading(f) => f.await.unwrap(),
+                    _ => panic!(),
+                };
+                assert_eq!(
+                    **material,
+                    Material {
+                        name: String::from("stone"),
+                        roughness: 0.5
+                    },
+                    "{}",
+                    file
+                );
+            }
+
+            let path = PathBuf::new().join("testfiles/broken.json");
+            matman.load::<Material, _>(&path).await.unwrap();
+            let error = match matman.get::<Material, _>(&path).await.unwrap() {
+                LoadStatus::Error(e) => e,
+                LoadStatus::Loading(f) => f.await.unwrap_err(),
+                _ => panic!(),
+            };
+            assert_eq!(
+                error.to_string(),
+                "testfiles/broken.json:3:5: expected `,` or `}`"
+            );
+        });
+    }
+
+    #[test]
+    fn validation() {
+        let pool = Arc::new(ThreadPool::new().unwrap());
+        let mut matman = ConfigManager::new(pool);
+        matman.register_schema::<Material>(
+            Schema::new()
+                .required("name")
+                .custom("name", |name| match name {
+                    Value::String(name) if name.is_empty() => {
+                        Err(String::from("must not be empty"))
+                    }
+                    _ => Ok(()),
+                })
+                .range("roughness", 0.0..=1.0)
+                .file_exists("surface.albedo"),
+        );
+        futures::executor::block_on(async {
+            let path = PathBuf::new().join("testfiles/invalid.ron");
+            matman.load::<Material, _>(&path).await.unwrap();
+            let error = match matman.get::<Material, _>(&path).await.unwrap() {
+                LoadStatus::Error(e) => e,
+                LoadStatus::Loading(f) => f.await.unwrap_err(),
+                _ => panic!(),
+            };
+            let report = error
+                .get_ref()
+                .and_then(|e| e.downcast_ref::<ValidationReport>())
+                .unwrap();
+            let lines = report
+                .problems
+                .iter()
+                .map(|problem| (problem.line, problem.field.as_str()))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                lines,
+                vec![
+                    (1, "name"),
+                    (2, "roughness"),
+                    (4, "surface.albedo"),
+                    (0, "")
+                ]
+            );
+            let path = PathBuf::new().join("testfiles/material.ron");
+            matman.load::<Material, _>(&path).await.unwrap();
+            if let LoadStatus::Loading(f) = matman.get::<Material, _>(&path).await.unwrap() {
+                f.await.unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn with_source() {
+        let pool = Arc::new(ThreadPool::new().unwrap());
+        let source =
+            MemorySource::new().with_file("stone.ron", &b"(name: \"stone\", roughness: 0.5)"[..]);
+        let mut matman = ConfigManager::with_source(pool, Arc::new(source));
+        matman.register_material::<Material>().unwrap();
+        futures::executor::block_on(async {
+            matman.load::<Material, _>("stone.ron").await.unwrap();
+            let material = match matman.get::<Material, _>("stone.ron").await.unwrap() {
+                LoadStatus::Loaded(file) => file,
+                LoadStatus::Loading(f) => f.await.unwrap(),
+                _ => panic!(),
+            };
+            assert_eq!(material.name, "stone");
+            // Memory has no local path to write to.
+            let error = matman.save("stone.ron", &**material).await.unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::Unsupported);
+        });
+    }
+
+    #[test]
+    fn save() {
+        let pool = Arc::new(ThreadPool::new().unwrap());
+        let mut matman = ConfigManager::new(pool);
+        matman.register_material::<Material>().unwrap();
+        let dir =
+            std::env::temp_dir().join(format!("async_filemanager_save_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("stone.ron");
+        fs::copy("testfiles/material.ron", &path).unwrap();
+        futures::executor::block_on(async {
+            matman.load::<Material, _>(&path).await.unwrap();
+            let mut material = match matman.get::<Material, _>(&path).await.unwrap() {
+                LoadStatus::Loaded(file) => Material::clone(&file),
+                LoadStatus::Loading(f) => Material::clone(&f.await.unwrap()),
+                _ => panic!(),
+            };
+            material.roughness = 0.25;
+            matman.save(&path, &material).await.unwrap();
+            assert_eq!(
+                fs::read_to_string(&path).unwrap(),
+                "(\n    name: \"stone\",\n    roughness: 0.25,\n)"
+            );
+            assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+            match matman.get::<Material, _>(&path).await.unwrap() {
+                LoadStatus::Loaded(file) => assert_eq!(**file, material),
+                _ => panic!(),
+            }
+
+            // The write by `save` does not trigger a reload, other writes do.
+            assert!(matman.reload(&path).is_empty());
+            fs::write(&path, "(name: \"marble\", roughness: 0.1)").unwrap();
+            assert_eq!(matman.reload(&path), vec![path.clone()]);
+            let file = match matman.get::<Material, _>(&path).await.unwrap() {
+                LoadStatus::Loaded(file) => file,
+                LoadStatus::Loading(f) => f.await.unwrap(),
+                _ => panic!(),
+            };
+            assert_eq!(file.name, "marble");
+
+            // Saving would drop `extends`, the file is left as it is.
+            let derived = dir.join("derived.ron");
+            fs::copy("testfiles/derived.ron", &derived).unwrap();
+            let error = matman.save(&derived, &material).await.unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+            assert_eq!(
+                fs::read(&derived).unwrap(),
+                fs::read("testfiles/derived.ron").unwrap()
+            );
+        });
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
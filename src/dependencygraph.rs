@@ -0,0 +1,294 @@
+use crate::{Identifier, LoadStatus};
+use futures::Future;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::{
+    fmt::Write,
+    io::Error,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Load state of a node in a DOT export.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NodeStatus {
+    #[default]
+    NotLoading,
+    Loading,
+    Loaded,
+    Error,
+}
+
+impl<T, F> From<&LoadStatus<T, F>> for NodeStatus
+where
+    T: Unpin,
+    F: Future<Output = Result<Arc<T>, Arc<Error>>>,
+{
+    fn from(status: &LoadStatus<T, F>) -> Self {
+        match status {
+            LoadStatus::NotLoading => NodeStatus::NotLoading,
+            LoadStatus::Loading(_) => NodeStatus::Loading,
+            LoadStatus::Loaded(_) => NodeStatus::Loaded,
+            LoadStatus::Error(_) => NodeStatus::Error,
+        }
+    }
+}
+
+impl NodeStatus {
+    fn color(self) -> &'static str {
+        match self {
+            NodeStatus::NotLoading => "lightgrey",
+            NodeStatus::Loading => "khaki",
+            NodeStatus::Loaded => "palegreen",
+            NodeStatus::Error => "salmon",
+        }
+    }
+}
+
+/// What a manager knows about one asset, used to label DOT nodes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct NodeInfo {
+    pub status: NodeStatus,
+    pub type_name: Option<&'static str>,
+    pub size: Option<u64>,
+    pub load_duration: Option<Duration>,
+}
+
+fn node_name(id: &Identifier) -> String {
+    match id {
+        Identifier::Path(path) => path.display().to_string(),
+        Identifier::Index(index) => format!("#{}", index),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn format_size(size: u64) -> String {
+    match size {
+        s if s >= 1 << 20 => format!("{:.1} MiB", s as f64 / (1 << 20) as f64),
+        s if s >= 1 << 10 => format!("{:.1} KiB", s as f64 / (1 << 10) as f64),
+        s => format!("{} B", s),
+    }
+}
+
+/// A graph shared by several managers, e.g. the file, GPU and RON managers of one scene,
+/// see `AsyncFileManager::set_graph`.
+pub type SharedGraph = Arc<Mutex<DependencyGraph>>;
+
+/// Parent/child relations between assets, a parent depends on (uses) its children.
+///
+/// Every asset also has a version, bumped by `touch` for it and everything using it.
+/// Managers sharing the graph compare it to the version they loaded to notice stale assets.
+#[derive(Debug, Default, Clone)]
+pub struct DependencyGraph {
+    children: HashMap<Identifier, HashSet<Identifier>>,
+    parents: HashMap<Identifier, HashSet<Identifier>>,
+    versions: HashMap<Identifier, u64>,
+}
+
+impl DependencyGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn add(&mut self, parent: Identifier, child: Identifier) {
+        self.children
+            .entry(parent.clone())
+            .or_default()
+            .insert(child.clone());
+        self.parents.entry(child).or_default().insert(parent);
+    }
+    /// Removes the asset and all relations it is part of.
+    pub fn remove(&mut self, id: &Identifier) {
+        self.clear_dependencies(id);
+        for parent in self.parents.remove(id).unwrap_or_default() {
+            if let Some(children) = self.children.get_mut(&parent) {
+                children.remove(id);
+            }
+        }
+    }
+    /// Removes the relations to everything `id` uses, e.g. before it is decoded again.
+    pub fn clear_dependencies(&mut self, id: &Identifier) {
+        for child in self.children.remove(id).unwrap_or_default() {
+            if let Some(parents) = self.parents.get_mut(&child) {
+                parents.remove(id);
+            }
+        }
+    }
+    /// Assets directly used by `id`.
+    pub fn dependencies(&self, id: &Identifier) -> impl Iterator<Item = &Identifier> {
+        self.children.get(id).into_iter().flatten()
+    }
+    /// Assets directly using `id`.
+    pub fn dependants(&self, id: &Identifier) -> impl Iterator<Item = &Identifier> {
+        self.parents.get(id).into_iter().flatten()
+    }
+    /// Every asset using `id` directly or indirectly, closest first, without `id` itself.
+    pub fn transitive_dependants(&self, id: &Identifier) -> Vec<Identifier> {
+        let mut visited = HashSet::new();
+        visited.insert(id.clone());
+        let mut queue = VecDeque::new();
+        queue.push_back(id.clone());
+        let mut dependants = Vec::new();
+        while let Some(current) = queue.pop_front() {
+            for parent in self.dependants(&current) {
+                if visited.insert(parent.clone()) {
+                    dependants.push(parent.clone());
+                    queue.push_back(parent.clone());
+                }
+            }
+        }
+        dependants
+    }
+    /// Marks `id` and every asset using it as changed.
+    /// Returns the assets using it, closest first, see `transitive_dependants`.
+    pub fn touch(&mut self, id: &Identifier) -> Vec<Identifier> {
+        let dependants = self.transitive_dependants(id);
+        for touched in std::iter::once(id).chain(&dependants) {
+            *self.versions.entry(touched.clone()).or_default() += 1;
+        }
+        dependants
+    }
+    /// How often `id` was touched.
+    pub fn version(&self, id: &Identifier) -> u64 {
+        self.versions.get(id).copied().unwrap_or_default()
+    }
+    /// Every asset that takes part in at least one relation.
+    pub fn ids(&self) -> impl Iterator<Item = &Identifier> {
+        self.children.keys().chain(
+            self.parents
+                .keys()
+                .filter(move |id| !self.children.contains_key(id)),
+        )
+    }
+    /// All `(parent, child)` relations.
+    pub fn edges(&self) -> impl Iterator<Item = (&Identifier, &Identifier)> {
+        self.children
+            .iter()
+            .flat_map(|(parent, children)| children.iter().map(move |child| (parent, child)))
+    }
+    /// Renders the graph as Graphviz DOT, with nodes coloured by load state and labelled
+    /// with what `info` reports about them. `nodes` adds assets without any relations.
+    pub fn to_dot<I, F>(&self, nodes: I, info: F) -> String
+    where
+        I: IntoIterator<Item = Identifier>,
+        F: Fn(&Identifier) -> NodeInfo,
+    {
+        let ids = self.ids().cloned().chain(nodes).collect::<BTreeSet<_>>();
+        let mut edges = self.edges().collect::<Vec<_>>();
+        edges.sort();
+
+        let mut dot = String::from("digraph assets {\n    node [shape=box, style=filled];\n");
+        for id in &ids {
+            let info = info(id);
+            let name = node_name(id);
+            let mut label = escape(&name);
+            if let Some(type_name) = info.type_name {
+                write!(label, "\\n{}", escape(type_name)).unwrap();
+            }
+            if let Some(size) = info.size {
+                write!(label, "\\n{}", format_size(size)).unwrap();
+            }
+            if let Some(duration) = info.load_duration {
+                write!(label, "\\n{:.1} ms", duration.as_secs_f64() * 1000.0).unwrap();
+            }
+            writeln!(
+                dot,
+                "    \"{}\" [label=\"{}\", fillcolor={}];",
+                escape(&name),
+                label,
+                info.status.color()
+            )
+            .unwrap();
+        }
+        for (parent, child) in edges {
+            writeln!(
+                dot,
+                "    \"{}\" -> \"{}\";",
+                escape(&node_name(parent)),
+                escape(&node_name(child))
+            )
+            .unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DependencyGraph, NodeInfo, NodeStatus};
+    use crate::Identifier;
+    use std::{path::PathBuf, time::Duration};
+
+    #[test]
+    fn dependants() {
+        let texture: Identifier = PathBuf::from("rock.png").into();
+        let material: Identifier = PathBuf::from("rock.mat.ron").into();
+        let level: Identifier = PathBuf::from("cave.level.ron").into();
+        let mut graph = DependencyGraph::new();
+        graph.add(material.clone(), texture.clone());
+        graph.add(level.clone(), material.clone());
+        graph.add(level.clone(), texture.clone());
+
+        let mut users = graph.dependants(&texture).cloned().collect::<Vec<_>>();
+        users.sort();
+        assert_eq!(users, vec![level.clone(), material.clone()]);
+        assert_eq!(
+            graph.transitive_dependants(&texture).len(),
+            2,
+            "level must only be listed once"
+        );
+        assert_eq!(graph.edges().count(), 3);
+        assert_eq!(graph.ids().count(), 3);
+
+        assert_eq!(graph.touch(&material), vec![level.clone()]);
+        assert_eq!(graph.version(&texture), 0);
+        assert_eq!((graph.version(&material), graph.version(&level)), (1, 1));
+
+        graph.remove(&material);
+        assert_eq!(graph.dependants(&texture).collect::<Vec<_>>(), vec![&level]);
+        assert_eq!(graph.dependencies(&material).count(), 0);
+    }
+
+    #[test]
+    fn cycles() {
+        let a: Identifier = 0.into();
+        let b: Identifier = 1.into();
+        let mut graph = DependencyGraph::new();
+        graph.add(a.clone(), b.clone());
+        graph.add(b.clone(), a.clone());
+        assert_eq!(graph.transitive_dependants(&a), vec![b]);
+    }
+
+    #[test]
+    fn dot() {
+        let texture: Identifier = PathBuf::from("rock.png").into();
+        let material: Identifier = PathBuf::from("rock \"mossy\".ron").into();
+        let mut graph = DependencyGraph::new();
+        graph.add(material.clone(), texture.clone());
+
+        let dot = graph.to_dot(vec![texture.clone(), 3.into()], |id| {
+            if id == &texture {
+                NodeInfo {
+                    status: NodeStatus::Loaded,
+                    type_name: Some("ImageData"),
+                    size: Some(3 << 20),
+                    load_duration: Some(Duration::from_micros(2500)),
+                }
+            } else {
+                NodeInfo::default()
+            }
+        });
+        assert_eq!(
+            dot,
+            "digraph assets {\n\
+            \x20   node [shape=box, style=filled];\n\
+            \x20   \"rock \\\"mossy\\\".ron\" [label=\"rock \\\"mossy\\\".ron\", fillcolor=lightgrey];\n\
+            \x20   \"rock.png\" [label=\"rock.png\\nImageData\\n3.0 MiB\\n2.5 ms\", fillcolor=palegreen];\n\
+            \x20   \"#3\" [label=\"#3\", fillcolor=lightgrey];\n\
+            \x20   \"rock \\\"mossy\\\".ron\" -> \"rock.png\";\n\
+            }\n"
+        );
+    }
+}
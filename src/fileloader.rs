@@ -1,49 +1,244 @@
+use crate::{
+    streamdecode::CHUNK_SIZE, ChunkReader, FileBytes, FileSystem, LoadProgress, ReadMode, Source,
+    StreamDecode,
+};
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::{uring, UringReader};
 use crossbeam_channel::{bounded, Receiver, TryRecvError};
 use futures::Future;
 use futures::{executor::ThreadPool, task::AtomicWaker};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::{
-    convert::TryFrom,
     io::{Error, ErrorKind},
-    marker::PhantomData,
     task::Poll,
+    time::{Duration, Instant},
 };
 
+/// Size and duration of reading and decoding one file.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LoadStats {
+    pub size: u64,
+    pub duration: Duration,
+    /// Layer of the source the file was read from, see `Source::read_layer`.
+    pub layer: Option<String>,
+}
+
+/// Decodes a file in place of its `StreamDecode` implementation, e.g. with settings of its manager
+/// or straight from mapped bytes.
+pub(crate) type Decoder<T> = Arc<dyn Fn(PathBuf, FileBytes) -> Result<T, Error> + Send + Sync>;
+
 pub struct FileLoadFuture<T>
 where
-    T: TryFrom<(PathBuf, Vec<u8>)>,
+    T: StreamDecode,
 {
     path: PathBuf,
     pool: Arc<ThreadPool>,
-    status: LoadStatus,
+    source: Arc<dyn Source>,
+    status: LoadStatus<T>,
     waker: Arc<AtomicWaker>,
-    _phantomdata: PhantomData<T>,
+    stats: Arc<Mutex<Option<LoadStats>>>,
+    decoder: Option<Decoder<T>>,
+    mode: ReadMode,
+    range: Option<Range<u64>>,
+    chunk_size: usize,
+    progress: Arc<LoadProgress>,
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    ring: Option<Arc<UringReader>>,
 }
 
 impl<T> FileLoadFuture<T>
 where
-    T: TryFrom<(PathBuf, Vec<u8>)>,
+    T: StreamDecode,
 {
+    /// Reads the file from the local filesystem.
     pub fn new<P: AsRef<Path>>(path: P, pool: Arc<ThreadPool>) -> Self {
+        Self::with_source(path, pool, Arc::new(FileSystem::default()))
+    }
+    pub fn with_source<P: AsRef<Path>>(
+        path: P,
+        pool: Arc<ThreadPool>,
+        source: Arc<dyn Source>,
+    ) -> Self {
         Self {
             path: path.as_ref().to_owned(),
             pool,
+            source,
             status: LoadStatus::Path,
             waker: Arc::new(AtomicWaker::new()),
-            _phantomdata: PhantomData::default(),
+            stats: Arc::new(Mutex::new(None)),
+            decoder: None,
+            mode: ReadMode::default(),
+            range: None,
+            chunk_size: CHUNK_SIZE,
+            progress: Arc::new(LoadProgress::new()),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            ring: None,
+        }
+    }
+    pub(crate) fn with_decoder(mut self, decoder: Option<Decoder<T>>) -> Self {
+        self.decoder = decoder;
+        self
+    }
+    /// Maps the file instead of reading it if `mode` asks to. Without a decoder taking the
+    /// mapped bytes, see `AsyncFileManager::set_decoder`, `StreamDecode` gets chunks of them.
+    pub fn with_read_mode(mut self, mode: ReadMode) -> Self {
+        self.mode = mode;
+        self
+    }
+    /// Reads only the bytes of `range` in the file, fewer if it ends before, and decodes them
+    /// as if they were the whole file.
+    pub fn with_range(mut self, range: Range<u64>) -> Self {
+        self.range = Some(range);
+        self
+    }
+    /// Hands the file to `StreamDecode` in chunks of `chunk_size` bytes, 1 MiB by default.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+    /// Reads the file with io_uring, in one batch with other loads doing so meanwhile, if the
+    /// source has it on the local filesystem and it is not mapped. It is opened and decoded on
+    /// the pool. The ring reads it whole into memory, so files or ranges above 64 MiB are read
+    /// on the pool in chunks instead, as they are where the kernel lacks io_uring.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub fn with_io_uring(mut self) -> Self {
+        self.ring = UringReader::shared();
+        self
+    }
+    /// The ring and the path on the local filesystem to read the file with, if any.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    fn ring_path(&self) -> Option<(Arc<UringReader>, PathBuf)> {
+        if self.mode != ReadMode::READ {
+            return None;
         }
+        Some((self.ring.clone()?, self.source.local_path(&self.path)?))
+    }
+    /// Filled in by the worker once the file is read and decoded.
+    pub fn stats(&self) -> Arc<Mutex<Option<LoadStats>>> {
+        self.stats.clone()
+    }
+    /// Updated by the worker while the file is read.
+    pub fn progress(&self) -> Arc<LoadProgress> {
+        self.progress.clone()
     }
 }
 
-enum LoadStatus {
+enum LoadStatus<T> {
     Path,
-    Loading(Receiver<Result<Vec<u8>, std::io::Error>>),
+    Loading(Receiver<Result<T, std::io::Error>>),
+}
+
+/// What the worker needs besides the path to read and decode a file.
+struct Load<T> {
+    source: Arc<dyn Source>,
+    mode: ReadMode,
+    range: Option<Range<u64>>,
+    chunk_size: usize,
+    stats: Arc<Mutex<Option<LoadStats>>>,
+    progress: Arc<LoadProgress>,
+    decoder: Option<Decoder<T>>,
+}
+
+/// Reads and decodes the file on the pool. Whole files that are read, not mapped, are streamed
+/// to `StreamDecode` while they are read, unless a decoder takes them at once.
+fn read_and_decode<T: StreamDecode>(load: Load<T>, path: PathBuf) -> Result<T, Error> {
+    let start = Instant::now();
+    let progress = load.progress.clone();
+    let chunk_size = load.chunk_size;
+    let (reader, layer) = match (load.range.clone(), load.mode) {
+        (Some(range), mode) => {
+            let (bytes, layer) = load.source.read_range_with(&path, range, mode)?;
+            let reader = ChunkReader::from_bytes(bytes, chunk_size, progress);
+            (reader, layer)
+        }
+        (None, ReadMode::READ) if load.decoder.is_none() => {
+            let file = load.source.open(&path)?;
+            let reader = ChunkReader::new(file.reader, file.size, chunk_size, progress);
+            (reader, file.layer)
+        }
+        (None, mode) => {
+            let (bytes, layer) = load.source.read_with(&path, mode)?;
+            let reader = ChunkReader::from_bytes(bytes, chunk_size, progress);
+            (reader, layer)
+        }
+    };
+    decode(load, path, reader, layer, start)
+}
+
+/// Decodes the file from `reader` and records the stats of its load, started at `start`.
+fn decode<T: StreamDecode>(
+    load: Load<T>,
+    path: PathBuf,
+    mut reader: ChunkReader,
+    layer: Option<String>,
+    start: Instant,
+) -> Result<T, Error> {
+    let result = match load.decoder {
+        Some(decoder) => reader.read_rest().and_then(|bytes| decoder(path, bytes)),
+        None => T::decode(path, &mut reader),
+    };
+    let progress = load.progress;
+    *load.stats.lock().unwrap() = Some(LoadStats {
+        size: progress.size().unwrap_or_else(|| progress.read()),
+        duration: start.elapsed(),
+        layer,
+    });
+    result
+}
+
+/// Opens the file at `local` on the pool, reads it with `ring` and decodes it on the pool.
+/// Files above `MAX_RING_READ` and all files once the ring thread stopped are read on the
+/// pool instead.
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+fn ring_read_and_decode<T, F>(
+    ring: Arc<UringReader>,
+    pool: Arc<ThreadPool>,
+    local: PathBuf,
+    load: Load<T>,
+    path: PathBuf,
+    finish: F,
+) where
+    T: StreamDecode + Send + 'static,
+    F: FnOnce(Result<T, Error>) + Send + 'static,
+{
+    let decode_pool = pool.clone();
+    pool.spawn_ok(async move {
+        let start = Instant::now();
+        let range = load.range.clone();
+        let (file, offset, len) = match uring::open(&local, range.clone()) {
+            Ok(opened) => opened,
+            Err(e) => return finish(Err(e)),
+        };
+        if len > uring::MAX_RING_READ {
+            return finish(read_and_decode(load, path));
+        }
+        let reply = Box::new(move |bytes: Result<Vec<u8>, Error>| {
+            decode_pool.spawn_ok(async move {
+                finish(bytes.and_then(|bytes| {
+                    let reader = ChunkReader::from_bytes(
+                        bytes.into(),
+                        load.chunk_size,
+                        load.progress.clone(),
+                    );
+                    decode(load, path, reader, None, start)
+                }));
+            })
+        });
+        if let Err(reply) = ring.read(file, offset, len, reply) {
+            let file_system = FileSystem::default();
+            reply(match range {
+                Some(range) => file_system.read_range(&local, range),
+                None => file_system.read(&local),
+            });
+        }
+    });
 }
 
 impl<T> Future for FileLoadFuture<T>
 where
-    T: TryFrom<(PathBuf, Vec<u8>)> + Unpin,
+    T: StreamDecode + Send + Unpin + 'static,
 {
     type Output = Result<Arc<T>, Arc<Error>>;
     fn poll(
@@ -56,24 +251,35 @@ where
                 let (tx, rx) = bounded(1);
                 self.waker.register(cx.waker());
                 let waker = self.waker.clone();
-                self.pool.spawn_ok(async move {
-                    tx.send(std::fs::read(path))
-                        .expect("Error forwarding loaded data!");
+                let load = Load {
+                    source: self.source.clone(),
+                    mode: self.mode,
+                    range: self.range.clone(),
+                    chunk_size: self.chunk_size,
+                    stats: self.stats.clone(),
+                    progress: self.progress.clone(),
+                    decoder: self.decoder.clone(),
+                };
+                let finish = move |result| {
+                    // The receiver is gone if the load was cancelled, nothing to forward to then.
+                    let _ = tx.send(result);
                     waker.wake();
-                });
+                };
+                #[cfg(all(target_os = "linux", feature = "io-uring"))]
+                {
+                    if let Some((ring, local)) = self.ring_path() {
+                        ring_read_and_decode(ring, self.pool.clone(), local, load, path, finish);
+                        self.get_mut().status = LoadStatus::Loading(rx);
+                        return Poll::Pending;
+                    }
+                }
+                self.pool
+                    .spawn_ok(async move { finish(read_and_decode(load, path)) });
                 self.get_mut().status = LoadStatus::Loading(rx);
                 std::task::Poll::Pending
             }
             LoadStatus::Loading(rx) => match rx.try_recv() {
-                Ok(r) => match r {
-                    Ok(v) => match T::try_from((path, v)) {
-                        Ok(f) => Poll::Ready(Ok(Arc::new(f))),
-                        Err(_e) => {
-                            Poll::Ready(Err(Arc::new(Error::new(ErrorKind::InvalidData, ""))))
-                        }
-                    },
-                    Err(e) => Poll::Ready(Err(Arc::new(e))),
-                },
+                Ok(r) => Poll::Ready(r.map(Arc::new).map_err(Arc::new)),
                 Err(TryRecvError::Empty) => {
                     self.waker.register(cx.waker());
                     Poll::Pending
@@ -87,8 +293,14 @@ where
 #[cfg(test)]
 mod tests {
     use super::FileLoadFuture;
+    use crate::{Metadata, Source};
     use futures::executor::ThreadPoolBuilder;
-    use std::{convert::TryFrom, path::PathBuf, sync::Arc};
+    use std::{
+        convert::TryFrom,
+        io::{Error, ErrorKind},
+        path::{Path, PathBuf},
+        sync::Arc,
+    };
 
     #[derive(Debug, Eq, PartialEq)]
     struct LoadedFile {
@@ -112,4 +324,32 @@ mod tests {
             let _f = l.await.unwrap();
         })
     }
+
+    /// Serves every path with its own name as content.
+    struct Echo;
+
+    impl Source for Echo {
+        fn read(&self, path: &Path) -> Result<Vec<u8>, Error> {
+            Ok(path.to_string_lossy().into_owned().into_bytes())
+        }
+        fn metadata(&self, path: &Path) -> Result<Metadata, Error> {
+            Ok(Metadata {
+                len: path.to_string_lossy().len() as u64,
+                modified: None,
+                is_dir: false,
+            })
+        }
+        fn read_dir(&self, _path: &Path) -> Result<Vec<PathBuf>, Error> {
+            Err(Error::new(ErrorKind::NotFound, "no directories"))
+        }
+    }
+
+    #[test]
+    fn source() {
+        let pool = Arc::new(ThreadPoolBuilder::new().create().unwrap());
+        let l = FileLoadFuture::<LoadedFile>::with_source("not/on/disk", pool, Arc::new(Echo));
+        async_std::task::block_on(async {
+            assert_eq!(l.await.unwrap().string, "not/on/disk");
+        })
+    }
 }
@@ -1,46 +1,379 @@
-use crate::{FileLoadFuture, LoadStatus};
+use crate::{
+    fileloader::Decoder, streamdecode::CHUNK_SIZE, DependencyGraph, FileBytes, FileLoadFuture,
+    FileSystem, Identifier, LoadProgress, LoadStats, LoadStatus, NodeInfo, NodeStatus, ReadMode,
+    SharedGraph, Source, StreamDecode,
+};
 use futures::executor::ThreadPool;
 use futures::{future::Shared, FutureExt};
 use std::{
-    collections::HashMap,
-    convert::TryFrom,
+    any::type_name,
+    collections::{HashMap, HashSet},
+    io::Error,
+    ops::Range,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{Arc, Mutex, MutexGuard},
     task::Poll,
 };
 
+/// A file and a byte range in it.
+type RangeKey = (PathBuf, Range<u64>);
+
+/// The paths among `ids`.
+fn paths(ids: Vec<Identifier>) -> Vec<PathBuf> {
+    ids.into_iter()
+        .filter_map(|id| match id {
+            Identifier::Path(path) => Some(path),
+            Identifier::Index(_) => None,
+        })
+        .collect()
+}
+
 #[allow(unused)]
 pub struct AsyncFileManager<T>
 where
-    T: TryFrom<(PathBuf, Vec<u8>)> + Unpin,
+    T: StreamDecode + Send + Unpin + 'static,
 {
     pool: Arc<ThreadPool>,
+    source: Arc<dyn Source>,
     loading: HashMap<PathBuf, Shared<FileLoadFuture<T>>>,
     cache: HashMap<PathBuf, Arc<T>>,
+    /// Loads of byte ranges, kept apart from whole files, see `load_range`.
+    range_loading: HashMap<RangeKey, Shared<FileLoadFuture<T>>>,
+    range_cache: HashMap<RangeKey, Arc<T>>,
+    range_stats: HashMap<RangeKey, Arc<Mutex<Option<LoadStats>>>>,
+    range_progress: HashMap<RangeKey, Arc<LoadProgress>>,
+    range_failed: HashSet<RangeKey>,
+    stats: HashMap<PathBuf, Arc<Mutex<Option<LoadStats>>>>,
+    progress: HashMap<PathBuf, Arc<LoadProgress>>,
+    failed: HashSet<PathBuf>,
+    graph: SharedGraph,
+    /// Version of every file in the graph when its load started, see `DependencyGraph::touch`.
+    versions: HashMap<PathBuf, u64>,
+    decoder: Option<Decoder<T>>,
+    mode: ReadMode,
+    chunk_size: usize,
 }
 
 impl<T> AsyncFileManager<T>
 where
-    T: TryFrom<(PathBuf, Vec<u8>)> + Unpin,
+    T: StreamDecode + Send + Unpin + 'static,
 {
+    /// Loads files from the local filesystem.
     #[allow(unused)]
     pub fn new(pool: Arc<ThreadPool>) -> Self {
+        Self::with_source(pool, Arc::new(FileSystem::default()))
+    }
+    #[allow(unused)]
+    pub fn with_source(pool: Arc<ThreadPool>, source: Arc<dyn Source>) -> Self {
         Self {
             pool,
+            source,
             loading: HashMap::new(),
             cache: HashMap::new(),
+            range_loading: HashMap::new(),
+            range_cache: HashMap::new(),
+            range_stats: HashMap::new(),
+            range_progress: HashMap::new(),
+            range_failed: HashSet::new(),
+            stats: HashMap::new(),
+            progress: HashMap::new(),
+            failed: HashSet::new(),
+            graph: SharedGraph::default(),
+            versions: HashMap::new(),
+            decoder: None,
+            mode: ReadMode::default(),
+            chunk_size: CHUNK_SIZE,
+        }
+    }
+    /// Records that `parent` uses `child`, reloading or evicting `child` then affects `parent` too.
+    #[allow(unused)]
+    pub fn add_dependency<P: AsRef<Path>, C: AsRef<Path>>(&mut self, parent: P, child: C) {
+        self.graph().add(
+            parent.as_ref().to_owned().into(),
+            child.as_ref().to_owned().into(),
+        );
+    }
+    #[allow(unused)]
+    pub fn graph(&self) -> MutexGuard<'_, DependencyGraph> {
+        self.graph.lock().unwrap()
+    }
+    /// Records relations in `graph` from now on, taking along those recorded so far.
+    /// Managers sharing a graph read a file again on their next `get` once a file it uses
+    /// is reloaded by any of them, e.g. a material once its texture is.
+    #[allow(unused)]
+    pub fn set_graph(&mut self, graph: SharedGraph) {
+        if !Arc::ptr_eq(&self.graph, &graph) {
+            let old = std::mem::replace(&mut self.graph, graph);
+            let old = old.lock().unwrap();
+            let mut graph = self.graph();
+            for (parent, child) in old.edges() {
+                graph.add(parent.clone(), child.clone());
+            }
+        }
+    }
+    #[allow(unused)]
+    pub fn shared_graph(&self) -> SharedGraph {
+        self.graph.clone()
+    }
+    #[allow(unused)]
+    pub fn source(&self) -> &Arc<dyn Source> {
+        &self.source
+    }
+    /// Size and read/decode time of the last finished load of the file.
+    #[allow(unused)]
+    pub fn stats<P: AsRef<Path>>(&self, path: P) -> Option<LoadStats> {
+        self.stats.get(path.as_ref())?.lock().unwrap().clone()
+    }
+    /// How much of the file its last load has read so far.
+    #[allow(unused)]
+    pub fn progress<P: AsRef<Path>>(&self, path: P) -> Option<Arc<LoadProgress>> {
+        self.progress.get(path.as_ref()).cloned()
+    }
+    /// Layer of the source the file was last read from, see `Vfs`.
+    #[allow(unused)]
+    pub fn layer<P: AsRef<Path>>(&self, path: P) -> Option<String> {
+        self.stats(path)?.layer
+    }
+    /// Like `stats`, of the last finished load of `range` in the file.
+    #[allow(unused)]
+    pub fn range_stats<P: AsRef<Path>>(&self, path: P, range: Range<u64>) -> Option<LoadStats> {
+        let key = (path.as_ref().to_owned(), range);
+        self.range_stats.get(&key)?.lock().unwrap().clone()
+    }
+    /// Like `progress`, of the last load of `range` in the file.
+    #[allow(unused)]
+    pub fn range_progress<P: AsRef<Path>>(
+        &self,
+        path: P,
+        range: Range<u64>,
+    ) -> Option<Arc<LoadProgress>> {
+        let key = (path.as_ref().to_owned(), range);
+        self.range_progress.get(&key).cloned()
+    }
+    /// Whether the last load of `range` in the file failed, until it is loaded again.
+    #[allow(unused)]
+    pub fn range_failed<P: AsRef<Path>>(&self, path: P, range: Range<u64>) -> bool {
+        self.range_failed
+            .contains(&(path.as_ref().to_owned(), range))
+    }
+    /// The load of the file in flight, if any.
+    pub(crate) fn loading(&self, path: &Path) -> Option<Shared<FileLoadFuture<T>>> {
+        self.loading.get(path).cloned()
+    }
+    pub(crate) fn node_info(&self, id: &Identifier) -> NodeInfo {
+        let path = match id {
+            Identifier::Path(path) => path,
+            Identifier::Index(_) => return NodeInfo::default(),
+        };
+        let status = if self.loading.contains_key(path) {
+            NodeStatus::Loading
+        } else if self.cache.contains_key(path) {
+            NodeStatus::Loaded
+        } else if self.failed.contains(path) {
+            NodeStatus::Error
+        } else {
+            NodeStatus::NotLoading
+        };
+        let stats = self.stats(path);
+        NodeInfo {
+            status,
+            type_name: Some(type_name::<T>()),
+            size: stats.as_ref().map(|stats| stats.size),
+            load_duration: stats.as_ref().map(|stats| stats.duration),
         }
     }
+    /// Renders every known file and its relations as Graphviz DOT, see `DependencyGraph::to_dot`.
+    #[allow(unused)]
+    pub fn to_dot(&self) -> String {
+        self.graph().to_dot(self.ids(), |id| self.node_info(id))
+    }
+    /// Every file that is loading, loaded or failed to load.
+    pub(crate) fn ids(&self) -> Vec<Identifier> {
+        self.loading
+            .keys()
+            .chain(self.cache.keys())
+            .chain(self.failed.iter())
+            .map(|path| path.clone().into())
+            .collect()
+    }
+    /// Reads the file again, along with every loaded file depending on it.
+    /// Returns the reloaded paths. Managers sharing the graph read their files depending on it
+    /// again on their next `get`.
+    #[allow(unused)]
+    pub async fn reload<P: AsRef<Path>>(&mut self, path: P) -> Vec<PathBuf> {
+        let mut reloaded = vec![path.as_ref().to_owned()];
+        self.invalidate(path.as_ref());
+        let dependants = self.graph().touch(&path.as_ref().to_owned().into());
+        for dependant in paths(dependants) {
+            if self.invalidate(&dependant) {
+                reloaded.push(dependant);
+            }
+        }
+        for path in &reloaded {
+            self.load(path).await;
+        }
+        reloaded
+    }
+    /// Drops the file and every file depending on it from the cache.
+    /// Returns the evicted paths.
+    #[allow(unused)]
+    pub fn evict<P: AsRef<Path>>(&mut self, path: P) -> Vec<PathBuf> {
+        let mut evicted = std::iter::once(path.as_ref().to_owned())
+            .chain(self.dependants(path.as_ref()))
+            .collect::<Vec<_>>();
+        evicted.retain(|path| self.invalidate(path));
+        evicted
+    }
+    /// Removes the file from the cache and cancels its load, returns whether it was present.
+    /// Ranges of the file are dropped as well.
+    pub(crate) fn invalidate(&mut self, path: &Path) -> bool {
+        self.range_loading.retain(|(ranged, _), _| ranged != path);
+        self.range_cache.retain(|(ranged, _), _| ranged != path);
+        self.range_failed.retain(|(ranged, _)| ranged != path);
+        self.range_stats.retain(|(ranged, _), _| ranged != path);
+        self.range_progress.retain(|(ranged, _), _| ranged != path);
+        self.stats.remove(path);
+        self.progress.remove(path);
+        self.versions.remove(path);
+        let loading = self.loading.remove(path).is_some();
+        let cached = self.cache.remove(path).is_some();
+        let failed = self.failed.remove(path);
+        loading || cached || failed
+    }
+    /// Swaps the cached `old` value for `new`, does nothing if `old` was reloaded or evicted meanwhile.
+    pub(crate) fn replace(&mut self, path: &Path, old: &Arc<T>, new: Arc<T>) {
+        if let Some(cached) = self.cache.get_mut(path) {
+            if Arc::ptr_eq(cached, old) {
+                *cached = new;
+            }
+        }
+    }
+    /// Caches `value` as the loaded file, replacing a loading or cached one.
+    pub(crate) fn insert(&mut self, path: &Path, value: Arc<T>) {
+        let version = self.graph().version(&path.to_owned().into());
+        self.versions.insert(path.to_owned(), version);
+        self.loading.remove(path);
+        self.failed.remove(path);
+        self.cache.insert(path.to_owned(), value);
+    }
+    /// Decodes files with `decoder` instead of `T::decode` from now on.
+    /// It gets the whole file at once, mapped files without copying them, see `set_read_mode`.
+    #[allow(unused)]
+    pub fn set_decoder<D>(&mut self, decoder: D)
+    where
+        D: Fn(PathBuf, FileBytes) -> Result<T, Error> + Send + Sync + 'static,
+    {
+        self.decoder = Some(Arc::new(decoder));
+    }
+    /// How files are read by `load` from now on, see `load_with_mode`.
+    #[allow(unused)]
+    pub fn set_read_mode(&mut self, mode: ReadMode) {
+        self.mode = mode;
+    }
+    /// Size of the chunks files are streamed to `T::decode` in from now on.
+    #[allow(unused)]
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = chunk_size;
+    }
+    fn future(&self, path: &Path, mode: ReadMode) -> FileLoadFuture<T> {
+        FileLoadFuture::with_source(path, self.pool.clone(), self.source.clone())
+            .with_decoder(self.decoder.clone())
+            .with_read_mode(mode)
+            .with_chunk_size(self.chunk_size)
+    }
+    /// Keeps the stats, progress and graph version of the latest load of the file.
+    fn track(&mut self, path: &Path, f: &FileLoadFuture<T>) {
+        let version = self.graph().version(&path.to_owned().into());
+        self.versions.insert(path.to_owned(), version);
+        self.stats.insert(path.to_owned(), f.stats());
+        self.progress.insert(path.to_owned(), f.progress());
+    }
+    /// Reads the file again if it was touched in the graph since its load started,
+    /// e.g. by another manager sharing the graph.
+    fn refresh(&mut self, path: &Path) {
+        let version = self.graph().version(&path.to_owned().into());
+        if matches!(self.versions.get(path), Some(seen) if *seen != version) {
+            self.invalidate(path);
+            self.schedule(path);
+        }
+    }
+    /// Queues a load that starts on the next `get`.
+    pub(crate) fn schedule(&mut self, path: &Path) {
+        if !self.cache.contains_key(path) && !self.loading.contains_key(path) {
+            let f = self.future(path, self.mode);
+            self.track(path, &f);
+            self.loading.insert(path.to_owned(), f.shared());
+        }
+    }
+    fn dependants(&self, path: &Path) -> Vec<PathBuf> {
+        paths(self.graph().transitive_dependants(&path.to_owned().into()))
+    }
     #[allow(unused)]
     pub async fn load<P: AsRef<Path>>(&mut self, path: P) {
+        self.load_with_mode(path, self.mode).await
+    }
+    /// Like `load`, reading the file as `mode` asks instead of the manager's read mode.
+    #[allow(unused)]
+    pub async fn load_with_mode<P: AsRef<Path>>(&mut self, path: P, mode: ReadMode) {
         if !self.cache.contains_key(path.as_ref()) && !self.loading.contains_key(path.as_ref()) {
-            let mut f = FileLoadFuture::new(path.as_ref(), self.pool.clone()).shared();
+            let f = self.future(path.as_ref(), mode);
+            self.track(path.as_ref(), &f);
+            self.failed.remove(path.as_ref());
+            let mut f = f.shared();
             futures::poll!(&mut f);
             self.loading.insert(path.as_ref().to_owned(), f);
         }
     }
+    /// Loads only the bytes of `range` in the file, fewer if it ends before, e.g. a header.
+    /// They are decoded as if they were the whole file and cached apart from it and other
+    /// ranges of it. The file is read as the manager's read mode asks, see `range_stats`.
+    #[allow(unused)]
+    pub async fn load_range<P: AsRef<Path>>(&mut self, path: P, range: Range<u64>) {
+        let key = (path.as_ref().to_owned(), range);
+        if !self.range_cache.contains_key(&key) && !self.range_loading.contains_key(&key) {
+            let f = self
+                .future(path.as_ref(), self.mode)
+                .with_range(key.1.clone());
+            self.range_stats.insert(key.clone(), f.stats());
+            self.range_progress.insert(key.clone(), f.progress());
+            self.range_failed.remove(&key);
+            let mut f = f.shared();
+            futures::poll!(&mut f);
+            self.range_loading.insert(key, f);
+        }
+    }
+    #[allow(unused)]
+    pub async fn get_range<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        range: Range<u64>,
+    ) -> LoadStatus<T, FileLoadFuture<T>> {
+        let key = (path.as_ref().to_owned(), range);
+        if let Some(f) = self.range_loading.get_mut(&key) {
+            if let Poll::Ready(result) = futures::poll!(f) {
+                self.range_loading.remove(&key);
+                match result {
+                    Ok(t) => {
+                        self.range_cache.insert(key, t.clone());
+                        LoadStatus::Loaded(t)
+                    }
+                    Err(e) => {
+                        self.range_failed.insert(key);
+                        LoadStatus::Error(e)
+                    }
+                }
+            } else {
+                LoadStatus::Loading(self.range_loading.get(&key).unwrap().clone())
+            }
+        } else if let Some(t) = self.range_cache.get(&key) {
+            LoadStatus::Loaded(t.clone())
+        } else {
+            LoadStatus::NotLoading
+        }
+    }
     #[allow(unused)]
     pub async fn get<P: AsRef<Path>>(&mut self, path: P) -> LoadStatus<T, FileLoadFuture<T>> {
+        self.refresh(path.as_ref());
         if let Some(f) = self.loading.get_mut(path.as_ref()) {
             if let Poll::Ready(result) = futures::poll!(f) {
                 self.loading.remove(path.as_ref());
@@ -51,7 +384,10 @@ where
                             .or_insert(t.clone());
                         LoadStatus::Loaded(t)
                     }
-                    Err(e) => LoadStatus::Error(e),
+                    Err(e) => {
+                        self.failed.insert(path.as_ref().to_owned());
+                        LoadStatus::Error(e)
+                    }
                 }
             } else {
                 LoadStatus::Loading(self.loading.get(path.as_ref()).unwrap().clone())
@@ -67,7 +403,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::AsyncFileManager;
-    use crate::LoadStatus;
+    use crate::{LoadStatus, ReadMode};
     use futures::executor::ThreadPoolBuilder;
     use std::{convert::TryFrom, path::PathBuf, sync::Arc};
 
@@ -109,4 +445,138 @@ mod tests {
             }
         });
     }
+
+    #[test]
+    fn reload() {
+        let pool = Arc::new(ThreadPoolBuilder::new().create().unwrap());
+        let child = PathBuf::new().join("benches/benchfiles/s01");
+        let parent = PathBuf::new().join("benches/benchfiles/s02");
+
+        let mut manager = AsyncFileManager::<LoadedFile>::new(pool);
+        manager.add_dependency(&parent, &child);
+        futures::executor::block_on(async {
+            for path in &[&child, &parent] {
+                manager.load(path).await;
+                if let LoadStatus::Loading(f) = manager.get(path).await {
+                    f.await.unwrap();
+                }
+            }
+            assert_eq!(
+                manager.reload(&child).await,
+                vec![child.clone(), parent.clone()]
+            );
+            assert!(manager.get(&parent).await != LoadStatus::NotLoading);
+
+            assert_eq!(manager.evict(&child), vec![child.clone(), parent.clone()]);
+            assert!(manager.get(&child).await == LoadStatus::NotLoading);
+            assert!(manager.get(&parent).await == LoadStatus::NotLoading);
+        });
+    }
+
+    #[test]
+    fn mapped() {
+        let pool = Arc::new(ThreadPoolBuilder::new().create().unwrap());
+        let paths = ["s01", "s02", "s03"]
+            .iter()
+            .map(|name| PathBuf::new().join("benches/benchfiles").join(name))
+            .collect::<Vec<_>>();
+
+        let mut manager = AsyncFileManager::<LoadedFile>::new(pool);
+        manager.set_decoder(|_path, bytes| {
+            Ok(LoadedFile {
+                string: format!("mapped: {}", bytes.is_mapped()),
+            })
+        });
+        futures::executor::block_on(async {
+            // The files are 182 bytes long.
+            manager.set_read_mode(unsafe { ReadMode::map_above(1024) });
+            manager.load(&paths[0]).await;
+            manager
+                .load_with_mode(&paths[1], unsafe { ReadMode::map() })
+                .await;
+            manager.set_read_mode(unsafe { ReadMode::map_above(100) });
+            manager.load(&paths[2]).await;
+            for (path, mapped) in paths.iter().zip(&[false, true, true]) {
+                let file = match manager.get(path).await {
+                    LoadStatus::Loaded(file) => file,
+                    LoadStatus::Loading(f) => f.await.unwrap(),
+                    _ => panic!(),
+                };
+                assert_eq!(file.string, format!("mapped: {}", mapped));
+            }
+        });
+    }
+
+    #[test]
+    fn range() {
+        let pool = Arc::new(ThreadPoolBuilder::new().create().unwrap());
+        let path = PathBuf::new().join("benches/benchfiles/s01");
+
+        let mut manager = AsyncFileManager::<LoadedFile>::new(pool);
+        futures::executor::block_on(async {
+            for (range, expected) in &[(2..6, "test"), (178..200, "test"), (300..400, "")] {
+                manager.load_range(&path, range.clone()).await;
+                let file = match manager.get_range(&path, range.clone()).await {
+                    LoadStatus::Loaded(file) => file,
+                    LoadStatus::Loading(f) => f.await.unwrap(),
+                    _ => panic!(),
+                };
+                assert_eq!(file.string, *expected);
+                assert!(manager.get_range(&path, range.clone()).await != LoadStatus::NotLoading);
+            }
+            // Ranges are cached apart from the whole file, but evicted with it.
+            assert!(manager.get(&path).await == LoadStatus::NotLoading);
+            manager.load(&path).await;
+            manager.evict(&path);
+            assert!(manager.get_range(&path, 2..6).await == LoadStatus::NotLoading);
+        });
+    }
+
+    #[test]
+    fn range_mapped() {
+        let pool = Arc::new(ThreadPoolBuilder::new().create().unwrap());
+        let path = PathBuf::new().join("benches/benchfiles/s01");
+        let missing = PathBuf::new().join("benches/benchfiles/missing");
+
+        let mut manager = AsyncFileManager::<LoadedFile>::new(pool);
+        manager.set_decoder(|_path, bytes| {
+            Ok(LoadedFile {
+                string: format!("{} {}", bytes.is_mapped(), String::from_utf8_lossy(&bytes)),
+            })
+        });
+        manager.set_read_mode(unsafe { ReadMode::map() });
+        futures::executor::block_on(async {
+            for (range, expected) in &[(2..6, "true test"), (178..200, "true test")] {
+                manager.load_range(&path, range.clone()).await;
+                let file = match manager.get_range(&path, range.clone()).await {
+                    LoadStatus::Loaded(file) => file,
+                    LoadStatus::Loading(f) => f.await.unwrap(),
+                    _ => panic!(),
+                };
+                assert_eq!(file.string, *expected);
+                let stats = manager.range_stats(&path, range.clone()).unwrap();
+                assert_eq!(stats.size, 4);
+                assert_eq!(
+                    manager.range_progress(&path, range.clone()).unwrap().read(),
+                    4
+                );
+            }
+
+            manager.load_range(&missing, 0..4).await;
+            if let LoadStatus::Loading(f) = manager.get_range(&missing, 0..4).await {
+                assert!(f.await.is_err());
+            }
+            assert!(!matches!(
+                manager.get_range(&missing, 0..4).await,
+                LoadStatus::Loaded(_) | LoadStatus::Loading(_)
+            ));
+            assert!(manager.range_failed(&missing, 0..4));
+            assert!(!manager.range_failed(&path, 2..6));
+
+            // Nothing of the evicted version is reported anymore.
+            manager.evict(&path);
+            assert!(manager.range_stats(&path, 2..6).is_none());
+            assert!(manager.range_progress(&path, 2..6).is_none());
+        });
+    }
 }
@@ -0,0 +1,186 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::{fmt, path::Path};
+
+/// Serialization formats understood by the `ConfigManager`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    Ron,
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    /// The format belonging to a file extension, without the leading dot.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "ron" => Some(Format::Ron),
+            "json" => Some(Format::Json),
+            "toml" => Some(Format::Toml),
+            "yaml" | "yml" => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+    /// The format of a file by its extension.
+    pub fn from_path(path: &Path) -> Result<Self, FormatError> {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(Format::from_extension)
+            .ok_or_else(|| FormatError::new(String::from("unknown format extension")))
+    }
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, FormatError> {
+        match self {
+            Format::Ron => ron::de::from_bytes(bytes).map_err(FormatError::from),
+            Format::Json => serde_json::from_slice(bytes)
+                .map_err(|e| FormatError::at(e.line(), e.column(), e.to_string())),
+            Format::Toml => {
+                let text =
+                    std::str::from_utf8(bytes).map_err(|e| FormatError::new(e.to_string()))?;
+                toml::from_str(text).map_err(|e| match e.line_col() {
+                    Some((line, col)) => FormatError::at(line + 1, col + 1, e.to_string()),
+                    None => FormatError::new(e.to_string()),
+                })
+            }
+            Format::Yaml => serde_yaml::from_slice(bytes).map_err(|e| match e.location() {
+                Some(location) => {
+                    FormatError::at(location.line(), location.column(), e.to_string())
+                }
+                None => FormatError::new(e.to_string()),
+            }),
+        }
+    }
+    /// Serializes `value` human readable, fields in declaration order.
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, FormatError> {
+        let text = match self {
+            Format::Ron => ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())
+                .map_err(FormatError::from)?,
+            Format::Json => {
+                serde_json::to_string_pretty(value).map_err(|e| FormatError::new(e.to_string()))?
+            }
+            Format::Toml => {
+                toml::to_string_pretty(value).map_err(|e| FormatError::new(e.to_string()))?
+            }
+            Format::Yaml => {
+                serde_yaml::to_string(value).map_err(|e| FormatError::new(e.to_string()))?
+            }
+        };
+        Ok(text.into_bytes())
+    }
+}
+
+/// A decode or encode error, `line` and `col` start at 1 and are 0 if unknown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatError {
+    pub line: usize,
+    pub col: usize,
+    pub message: String,
+}
+
+impl FormatError {
+    fn new(message: String) -> Self {
+        Self {
+            line: 0,
+            col: 0,
+            message,
+        }
+    }
+    /// JSON, TOML and YAML errors end their messages in the position they report,
+    /// that suffix is cut off as the position is kept separately.
+    fn at(line: usize, col: usize, message: String) -> Self {
+        let suffix = format!(" at line {} column {}", line, col);
+        let message = match message.strip_suffix(suffix.as_str()) {
+            Some(message) => message.to_owned(),
+            None => message,
+        };
+        Self { line, col, message }
+    }
+}
+
+impl From<ron::Error> for FormatError {
+    fn from(e: ron::Error) -> Self {
+        Self {
+            line: e.position.line,
+            col: e.position.col,
+            message: e.code.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}:{}: {}", self.line, self.col, self.message)
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+#[cfg(test)]
+mod tests {
+    use super::Format;
+    use serde::{Deserialize, Serialize};
+    use std::path::Path;
+
+    #[derive(Debug, Deserialize, Serialize, PartialEq)]
+    struct Surface {
+        roughness: f32,
+        tags: Vec<String>,
+    }
+
+    #[derive(Debug, Deserialize, Serialize, PartialEq)]
+    struct Material {
+        name: String,
+        surface: Surface,
+    }
+
+    #[test]
+    fn round_trip() {
+        let material = Material {
+            name: String::from("stone"),
+            surface: Surface {
+                roughness: 0.5,
+                tags: vec![String::from("rough"), String::from("grey")],
+            },
+        };
+        for format in [Format::Ron, Format::Json, Format::Toml, Format::Yaml].iter() {
+            let bytes = format.encode(&material).unwrap();
+            assert_eq!(
+                format.decode::<Material>(&bytes).unwrap(),
+                material,
+                "{:?}",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn selection() {
+        assert_eq!(Format::from_path(Path::new("a/b.json")), Ok(Format::Json));
+        assert_eq!(Format::from_path(Path::new("b.mat.toml")), Ok(Format::Toml));
+        assert_eq!(Format::from_path(Path::new("b.yml")), Ok(Format::Yaml));
+        assert_eq!(Format::from_path(Path::new("b.mat.ron")), Ok(Format::Ron));
+        assert!(Format::from_path(Path::new("b.mat")).is_err());
+    }
+
+    #[test]
+    fn positions() {
+        let error = Format::Json
+            .decode::<Material>(b"{\n  \"name\": \"stone\"\n  \"surface\": {}\n}")
+            .unwrap_err();
+        assert_eq!((error.line, error.col), (3, 3));
+        assert_eq!(error.to_string(), "3:3: expected `,` or `}`");
+        let error = Format::Toml
+            .decode::<Material>(b"name = \"stone\"\nsurface = ")
+            .unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(!error.message.contains(" at line "));
+        let error = Format::Yaml
+            .decode::<Material>(b"name: stone\nsurface: ]\n")
+            .unwrap_err();
+        assert_eq!((error.line, error.col), (2, 10));
+        assert!(!error.message.contains(" at line "));
+    }
+}
@@ -0,0 +1,14 @@
+use std::path::Path;
+use wgpu::{Device, Queue};
+
+/// CPU-side data that can be uploaded into a GPU resource.
+pub trait GpuAsset: Send + Sync + 'static {
+    type Resource: Send + Sync + Unpin + 'static;
+    /// Estimated GPU memory of the uploaded resource in bytes.
+    fn gpu_size(&self) -> u64;
+    fn upload(&self, device: &Device, queue: &Queue) -> Self::Resource;
+    /// Path the asset was read from, used to find the resource to replace on reload.
+    fn source(&self) -> Option<&Path> {
+        None
+    }
+}
@@ -1,67 +1,109 @@
-use super::imagedata::ImageData;
-use crossbeam_channel::{bounded, Receiver, TryRecvError};
+use super::gpuasset::GpuAsset;
+use crossbeam_channel::{bounded, Receiver, Sender, TryRecvError};
 use futures::Future;
 use futures::{executor::ThreadPool, task::AtomicWaker};
-use std::{sync::Arc, task::Poll};
+use std::{
+    sync::{Arc, Mutex},
+    task::Poll,
+    time::{Duration, Instant},
+};
 
-use wgpu::{Device, Queue, Texture};
+use wgpu::{Device, Queue};
 
-pub struct GpuLoadFuture {
-    imgdata: Arc<ImageData>,
+/// Duration of an upload, filled in by the worker once it is done.
+pub(crate) type UploadDuration = Arc<Mutex<Option<Duration>>>;
+
+pub struct GpuLoadFuture<A>
+where
+    A: GpuAsset,
+{
+    asset: Arc<A>,
     device: Arc<Device>,
     queue: Arc<Queue>,
     pool: Arc<ThreadPool>,
     waker: Arc<AtomicWaker>,
-    status: LoadStatus,
+    duration: UploadDuration,
+    status: LoadStatus<A::Resource>,
 }
 #[allow(unused)]
-impl GpuLoadFuture {
+impl<A> GpuLoadFuture<A>
+where
+    A: GpuAsset,
+{
     pub fn new(
-        imgdata: Arc<ImageData>,
+        asset: Arc<A>,
         device: Arc<Device>,
         queue: Arc<Queue>,
         pool: Arc<ThreadPool>,
     ) -> Self {
         Self {
-            imgdata,
+            asset,
             device,
             queue,
             pool,
             waker: Arc::new(AtomicWaker::new()),
-            status: LoadStatus::ImageData,
+            duration: Arc::new(Mutex::new(None)),
+            status: LoadStatus::Asset,
         }
     }
+    /// A future that is resolved through the returned sender instead of uploading on the pool.
+    pub(crate) fn batched(
+        asset: Arc<A>,
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        pool: Arc<ThreadPool>,
+    ) -> (Self, Sender<Arc<A::Resource>>) {
+        let (tx, rx) = bounded(1);
+        let mut f = Self::new(asset, device, queue, pool);
+        f.status = LoadStatus::Uploading(rx);
+        (f, tx)
+    }
+    pub(crate) fn waker(&self) -> Arc<AtomicWaker> {
+        self.waker.clone()
+    }
+    /// Filled in by the worker once the asset is uploaded, for batched uploads the time
+    /// of the whole batch.
+    pub fn upload_duration(&self) -> UploadDuration {
+        self.duration.clone()
+    }
 }
 
-enum LoadStatus {
-    ImageData,
-    Uploading(Receiver<Arc<Texture>>),
+enum LoadStatus<R> {
+    Asset,
+    Uploading(Receiver<Arc<R>>),
 }
 
-impl Future for GpuLoadFuture {
-    type Output = Result<Arc<Texture>, Arc<std::io::Error>>;
+impl<A> Future for GpuLoadFuture<A>
+where
+    A: GpuAsset,
+{
+    type Output = Result<Arc<A::Resource>, Arc<std::io::Error>>;
     fn poll(
         self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Self::Output> {
         match &self.status {
-            LoadStatus::ImageData => {
+            LoadStatus::Asset => {
                 let (tx, rx) = bounded(1);
                 self.waker.register(cx.waker());
                 let waker = self.waker.clone();
-                let imgdata = self.imgdata.clone();
+                let asset = self.asset.clone();
                 let device = self.device.clone();
                 let queue = self.queue.clone();
+                let duration = self.duration.clone();
                 self.pool.spawn_ok(async move {
-                    tx.send(Arc::new(imgdata.upload(device, queue)))
-                        .expect("Error forwarding loaded data!");
+                    let start = Instant::now();
+                    let resource = asset.upload(&device, &queue);
+                    *duration.lock().unwrap() = Some(start.elapsed());
+                    // The receiver is gone if the upload was superseded, nothing to forward to then.
+                    let _ = tx.send(Arc::new(resource));
                     waker.wake();
                 });
                 self.get_mut().status = LoadStatus::Uploading(rx);
                 std::task::Poll::Pending
             }
             LoadStatus::Uploading(rx) => match rx.try_recv() {
-                Ok(texture) => Poll::Ready(Ok(texture)),
+                Ok(resource) => Poll::Ready(Ok(resource)),
                 Err(TryRecvError::Empty) => {
                     self.waker.register(cx.waker());
                     Poll::Pending
@@ -77,8 +119,8 @@ impl Future for GpuLoadFuture {
 
 #[cfg(test)]
 mod tests {
-    use super::{GpuLoadFuture, ImageData};
-    use crate::{AsyncFileManager, LoadStatus};
+    use super::GpuLoadFuture;
+    use crate::{imagedata::ImageData, AsyncFileManager, LoadStatus};
     use futures::executor::ThreadPoolBuilder;
     use std::{path::PathBuf, sync::Arc};
 
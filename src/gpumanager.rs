@@ -1,20 +1,117 @@
-use crate::{gpuloader::GpuLoadFuture, imagedata::ImageData, Identifier, LoadStatus};
+use crate::{
+    gpuasset::GpuAsset,
+    gpuloader::{GpuLoadFuture, UploadDuration},
+    imagedata::ImageData,
+    staging::{BatchStats, StagingPlan},
+    DependencyGraph, Identifier, LoadStatus, NodeInfo, NodeStatus, SharedGraph,
+};
 
-use futures::executor::ThreadPool;
+use crossbeam_channel::Sender;
+use futures::{executor::ThreadPool, task::AtomicWaker};
 use futures::{future::Shared, FutureExt};
-use std::{collections::HashMap, sync::Arc, task::Poll};
-use wgpu::{Device, Queue, Texture};
+use std::{
+    any::type_name,
+    collections::{HashMap, HashSet},
+    io::Error,
+    path::PathBuf,
+    sync::{Arc, MutexGuard},
+    task::Poll,
+    time::{Duration, Instant},
+};
+use wgpu::{BufferUsage, Device, Extent3d, Queue, TextureFormat};
+
+/// The duration recorded by the worker, the upload finished before its future resolved.
+fn uploaded_in(duration: &UploadDuration) -> Duration {
+    duration.lock().unwrap().unwrap_or_default()
+}
+
+/// Estimated GPU memory of a texture in bytes, summed over all mip levels.
+pub fn estimate_texture_size(extent: Extent3d, format: TextureFormat, mip_level_count: u32) -> u64 {
+    let texel_size = match format {
+        TextureFormat::R8Unorm
+        | TextureFormat::R8Snorm
+        | TextureFormat::R8Uint
+        | TextureFormat::R8Sint => 1,
+        TextureFormat::R16Uint
+        | TextureFormat::R16Sint
+        | TextureFormat::R16Float
+        | TextureFormat::Rg8Unorm
+        | TextureFormat::Rg8Snorm
+        | TextureFormat::Rg8Uint
+        | TextureFormat::Rg8Sint => 2,
+        TextureFormat::Rg32Uint
+        | TextureFormat::Rg32Sint
+        | TextureFormat::Rg32Float
+        | TextureFormat::Rgba16Uint
+        | TextureFormat::Rgba16Sint
+        | TextureFormat::Rgba16Float => 8,
+        TextureFormat::Rgba32Uint | TextureFormat::Rgba32Sint | TextureFormat::Rgba32Float => 16,
+        // 32 bit formats; compressed formats are over-estimated with this.
+        _ => 4,
+    };
+    (0..mip_level_count)
+        .map(|level| {
+            let width = (extent.width >> level).max(1) as u64;
+            let height = (extent.height >> level).max(1) as u64;
+            width * height * extent.depth as u64 * texel_size
+        })
+        .sum()
+}
+
+struct CacheEntry<R> {
+    resource: Arc<R>,
+    size: u64,
+    last_used: u64,
+    upload_duration: Duration,
+}
+
+/// Uploads a batch through one staging buffer and command submission.
+type FlushBatch<A> = fn(&Device, &Queue, Vec<PendingUpload<A>>) -> BatchStats;
+
+/// Batched uploads are flushed once this many are queued unless set otherwise.
+const BATCH_LIMIT: usize = 64;
+
+struct PendingUpload<A>
+where
+    A: GpuAsset,
+{
+    id: Identifier,
+    asset: Arc<A>,
+    duration: UploadDuration,
+    sender: Sender<Arc<A::Resource>>,
+    waker: Arc<AtomicWaker>,
+}
 
 #[allow(unused)]
-pub struct AsyncGpuManager {
+pub struct AsyncGpuManager<A = ImageData>
+where
+    A: GpuAsset,
+{
     device: Arc<Device>,
     queue: Arc<Queue>,
     pool: Arc<ThreadPool>,
-    loading: HashMap<Identifier, Shared<GpuLoadFuture>>,
-    cache: HashMap<Identifier, Arc<Texture>>,
+    loading: HashMap<Identifier, (Shared<GpuLoadFuture<A>>, u64, UploadDuration)>,
+    reloading: HashMap<Identifier, (Shared<GpuLoadFuture<A>>, u64, UploadDuration)>,
+    cache: HashMap<Identifier, CacheEntry<A::Resource>>,
+    failed: HashSet<Identifier>,
+    /// Error of the last load or reload of a resource, until one succeeds.
+    errors: HashMap<Identifier, Arc<Error>>,
+    sources: HashMap<PathBuf, Identifier>,
+    /// Version of every resource in the graph when it was first uploaded.
+    versions: HashMap<Identifier, u64>,
+    graph: SharedGraph,
+    batch: Vec<PendingUpload<A>>,
+    batch_limit: usize,
+    flush_batch: Option<FlushBatch<A>>,
+    budget: Option<u64>,
+    usage: u64,
+    tick: u64,
 }
 
-impl AsyncGpuManager {
+impl<A> AsyncGpuManager<A>
+where
+    A: GpuAsset,
+{
     #[allow(unused)]
     pub fn new(pool: Arc<ThreadPool>, device: Arc<Device>, queue: Arc<Queue>) -> Self {
         Self {
@@ -22,92 +119,509 @@ impl AsyncGpuManager {
             queue,
             pool,
             loading: HashMap::new(),
+            reloading: HashMap::new(),
             cache: HashMap::new(),
+            failed: HashSet::new(),
+            errors: HashMap::new(),
+            sources: HashMap::new(),
+            versions: HashMap::new(),
+            graph: SharedGraph::default(),
+            batch: Vec::new(),
+            batch_limit: BATCH_LIMIT,
+            flush_batch: None,
+            budget: None,
+            usage: 0,
+            tick: 0,
         }
     }
+    /// Sets the VRAM budget in bytes, `None` disables eviction.
+    #[allow(unused)]
+    pub fn set_budget(&mut self, budget: Option<u64>) {
+        self.budget = budget;
+        self.evict();
+    }
+    #[allow(unused)]
+    pub fn budget(&self) -> Option<u64> {
+        self.budget
+    }
+    /// Estimated GPU memory of all cached resources in bytes.
     #[allow(unused)]
-    pub async fn load(&mut self, id: &Identifier, img: Arc<ImageData>) {
+    pub fn usage(&self) -> u64 {
+        self.usage
+    }
+    #[allow(unused)]
+    pub async fn load(&mut self, id: &Identifier, asset: Arc<A>) {
         if !self.cache.contains_key(&id) && !self.loading.contains_key(&id) {
-            let mut f = GpuLoadFuture::new(
-                img,
+            self.track_source(id, &asset);
+            let size = asset.gpu_size();
+            let f = GpuLoadFuture::new(
+                asset,
                 self.device.clone(),
                 self.queue.clone(),
                 self.pool.clone(),
-            )
-            .shared();
+            );
+            let duration = f.upload_duration();
+            let mut f = f.shared();
             futures::poll!(&mut f);
-            self.loading.insert(id.clone(), f);
+            self.loading.insert(id.clone(), (f, size, duration));
+        }
+    }
+    /// Uploads a newer version of an asset in place of the resource loaded from the same source path.
+    /// The old resource is served until the replacement is uploaded, then the generation is bumped.
+    /// Returns the `Identifier` of the replaced resource, `None` if the source path is unknown
+    /// or its resource is neither loading nor loaded.
+    #[allow(unused)]
+    pub async fn reload(&mut self, asset: Arc<A>) -> Option<Identifier> {
+        let id = self.sources.get(asset.source()?)?.clone();
+        if !self.cache.contains_key(&id) && !self.loading.contains_key(&id) {
+            return None;
+        }
+        let size = asset.gpu_size();
+        let f = GpuLoadFuture::new(
+            asset,
+            self.device.clone(),
+            self.queue.clone(),
+            self.pool.clone(),
+        );
+        let duration = f.upload_duration();
+        let mut f = f.shared();
+        futures::poll!(&mut f);
+        if self.cache.contains_key(&id) {
+            self.reloading.insert(id.clone(), (f, size, duration));
+        } else {
+            self.loading.insert(id.clone(), (f, size, duration));
         }
+        Some(id)
+    }
+    /// Number of times the resource or anything it depends on has been replaced or reloaded,
+    /// by this manager or another one sharing the graph. `None` if it was never uploaded.
+    #[allow(unused)]
+    pub fn generation(&self, id: &Identifier) -> Option<u64> {
+        let uploaded = self.versions.get(id)?;
+        Some(self.graph().version(id) - uploaded)
+    }
+    /// Why the last load or reload of the resource failed, `None` once one succeeds.
+    /// A failed reload leaves the previous resource cached, `get` keeps serving it.
+    #[allow(unused)]
+    pub fn last_error(&self, id: &Identifier) -> Option<Arc<Error>> {
+        self.errors.get(id).cloned()
     }
+    /// Flushes the pending batch first if the upload of `id` waits in it.
     #[allow(unused)]
-    pub async fn get(&mut self, id: &Identifier) -> LoadStatus<Texture, GpuLoadFuture> {
-        if let Some(f) = self.loading.get_mut(id) {
+    pub async fn get(&mut self, id: &Identifier) -> LoadStatus<A::Resource, GpuLoadFuture<A>> {
+        self.tick += 1;
+        if self.batch.iter().any(|upload| &upload.id == id) {
+            self.flush();
+        }
+        if let Some((f, size, duration)) = self.reloading.get_mut(id) {
+            let (size, duration) = (*size, duration.clone());
+            if let Poll::Ready(result) = futures::poll!(f) {
+                self.reloading.remove(id);
+                match result {
+                    Ok(t) => self.insert(id, t, size, uploaded_in(&duration)),
+                    Err(e) => {
+                        self.errors.insert(id.clone(), e);
+                    }
+                }
+            }
+        }
+        if let Some((f, size, duration)) = self.loading.get_mut(id) {
+            let (size, duration) = (*size, duration.clone());
             if let Poll::Ready(result) = futures::poll!(f) {
                 self.loading.remove(id);
                 match result {
                     Ok(t) => {
-                        self.cache.entry(id.clone()).or_insert(t.clone());
+                        self.insert(id, t.clone(), size, uploaded_in(&duration));
                         LoadStatus::Loaded(t)
                     }
-                    Err(e) => LoadStatus::Error(e),
+                    Err(e) => {
+                        self.failed.insert(id.clone());
+                        self.errors.insert(id.clone(), e.clone());
+                        LoadStatus::Error(e)
+                    }
                 }
             } else {
-                LoadStatus::Loading(self.loading.get(id).unwrap().clone())
+                LoadStatus::Loading(self.loading.get(id).unwrap().0.clone())
             }
-        } else if let Some(f) = self.cache.get(id) {
-            LoadStatus::Loaded(f.clone())
+        } else if let Some(entry) = self.cache.get_mut(id) {
+            entry.last_used = self.tick;
+            LoadStatus::Loaded(entry.resource.clone())
         } else {
             LoadStatus::NotLoading
         }
     }
+    /// Number of queued batched uploads at which they are flushed right away, 64 by default.
+    #[allow(unused)]
+    pub fn set_batch_limit(&mut self, limit: usize) {
+        self.batch_limit = limit.max(1);
+        if self.batch.len() >= self.batch_limit {
+            self.flush();
+        }
+    }
+    /// Uploads all queued batched uploads through one staging buffer and a single command submission.
+    #[allow(unused)]
+    pub fn flush(&mut self) -> BatchStats {
+        let batch = std::mem::take(&mut self.batch);
+        match self.flush_batch {
+            Some(flush_batch) if !batch.is_empty() => flush_batch(&self.device, &self.queue, batch),
+            _ => BatchStats::default(),
+        }
+    }
+    fn track_source(&mut self, id: &Identifier, asset: &A) {
+        if let Some(path) = asset.source() {
+            self.sources.insert(path.to_owned(), id.clone());
+        }
+    }
+    /// Caches a resource that was uploaded elsewhere, e.g. by a `TextureLoadFuture`.
+    pub(crate) fn insert(
+        &mut self,
+        id: &Identifier,
+        resource: Arc<A::Resource>,
+        size: u64,
+        upload_duration: Duration,
+    ) {
+        self.failed.remove(id);
+        self.errors.remove(id);
+        let entry = CacheEntry {
+            resource,
+            size,
+            last_used: self.tick,
+            upload_duration,
+        };
+        if let Some(old) = self.cache.insert(id.clone(), entry) {
+            self.usage -= old.size;
+            // Dependants have to rebuild whatever they built from the old resource.
+            self.graph().touch(id);
+        }
+        self.usage += size;
+        if !self.versions.contains_key(id) {
+            let version = self.graph().version(id);
+            self.versions.insert(id.clone(), version);
+        }
+        self.evict();
+    }
+    /// Estimated GPU memory of the cached resource, `None` if it is not cached.
+    pub(crate) fn cached_size(&self, id: &Identifier) -> Option<u64> {
+        self.cache.get(id).map(|entry| entry.size)
+    }
+    /// Drops least recently used resources nobody else holds until the usage fits the budget.
+    fn evict(&mut self) {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return,
+        };
+        if self.usage <= budget {
+            return;
+        }
+        let mut unused = self
+            .cache
+            .iter()
+            .filter(|(_, entry)| Arc::strong_count(&entry.resource) == 1)
+            .map(|(id, entry)| (entry.last_used, id.clone()))
+            .collect::<Vec<_>>();
+        unused.sort();
+        for (_, id) in unused {
+            if self.usage <= budget {
+                break;
+            }
+            self.remove(&id);
+        }
+    }
+    /// Records that `parent` was built from `child`, so replacing or evicting `child` affects `parent`.
+    #[allow(unused)]
+    pub fn add_dependency(&mut self, parent: &Identifier, child: &Identifier) {
+        self.graph().add(parent.clone(), child.clone());
+    }
+    #[allow(unused)]
+    pub fn graph(&self) -> MutexGuard<'_, DependencyGraph> {
+        self.graph.lock().unwrap()
+    }
+    /// Records relations in `graph` from now on, taking along those recorded so far.
+    /// A resource shared with other managers, e.g. the `AsyncFileManager` of its images,
+    /// advances its `generation` when a file it depends on is reloaded there.
+    #[allow(unused)]
+    pub fn set_graph(&mut self, graph: SharedGraph) {
+        if !Arc::ptr_eq(&self.graph, &graph) {
+            let old = std::mem::replace(&mut self.graph, graph);
+            let old = old.lock().unwrap();
+            let mut graph = self.graph.lock().unwrap();
+            for (parent, child) in old.edges() {
+                graph.add(parent.clone(), child.clone());
+            }
+            // Keep generations counting from where they are.
+            for (id, uploaded) in self.versions.iter_mut() {
+                *uploaded = graph.version(id) + *uploaded - old.version(id);
+            }
+        }
+    }
+    #[allow(unused)]
+    pub fn shared_graph(&self) -> SharedGraph {
+        self.graph.clone()
+    }
+    /// Renders every known resource and its relations as Graphviz DOT, labelled with the
+    /// estimated GPU size and the time the upload took on the worker.
+    #[allow(unused)]
+    pub fn to_dot(&self) -> String {
+        let ids = self
+            .loading
+            .keys()
+            .chain(self.cache.keys())
+            .chain(self.failed.iter())
+            .cloned();
+        self.graph().to_dot(ids, |id| {
+            if let Some((_, size, _)) = self.loading.get(id) {
+                NodeInfo {
+                    status: NodeStatus::Loading,
+                    type_name: Some(type_name::<A::Resource>()),
+                    size: Some(*size),
+                    load_duration: None,
+                }
+            } else if let Some(entry) = self.cache.get(id) {
+                NodeInfo {
+                    status: NodeStatus::Loaded,
+                    type_name: Some(type_name::<A::Resource>()),
+                    size: Some(entry.size),
+                    load_duration: Some(entry.upload_duration),
+                }
+            } else if self.failed.contains(id) {
+                NodeInfo {
+                    status: NodeStatus::Error,
+                    type_name: Some(type_name::<A::Resource>()),
+                    ..NodeInfo::default()
+                }
+            } else {
+                NodeInfo::default()
+            }
+        })
+    }
+    /// Drops the resource, its load or reload in flight, and every cached resource depending
+    /// on it that nobody else holds. Returns the removed `Identifier`s.
+    #[allow(unused)]
+    pub fn remove(&mut self, id: &Identifier) -> Vec<Identifier> {
+        let dependants = self.graph().transitive_dependants(id);
+        let mut removed = std::iter::once(id.clone())
+            .chain(dependants.into_iter().filter(|dependant| {
+                matches!(self.cache.get(dependant), Some(entry) if Arc::strong_count(&entry.resource) == 1)
+            }))
+            .collect::<Vec<_>>();
+        removed.retain(|id| self.forget(id));
+        removed
+    }
+    /// Drops everything kept for `id`. Its load, reload or batched upload in flight is dropped
+    /// with it, so it can not complete into the cache later. Returns whether there was any.
+    fn forget(&mut self, id: &Identifier) -> bool {
+        let loading = self.loading.remove(id).is_some();
+        let reloading = self.reloading.remove(id).is_some();
+        self.failed.remove(id);
+        self.errors.remove(id);
+        self.versions.remove(id);
+        self.sources.retain(|_, source| source != id);
+        self.batch.retain(|upload| &upload.id != id);
+        let cached = match self.cache.remove(id) {
+            Some(entry) => {
+                self.usage -= entry.size;
+                true
+            }
+            None => false,
+        };
+        loading || reloading || cached
+    }
+}
+
+impl AsyncGpuManager<ImageData> {
+    /// Queues an upload to be flushed with others instead of uploading it on the pool.
+    /// The batch is flushed by `flush`, once it holds `set_batch_limit` uploads, or when
+    /// `get` asks for one of them.
+    #[allow(unused)]
+    pub async fn load_batched(&mut self, id: &Identifier, img: Arc<ImageData>) {
+        if !self.cache.contains_key(id) && !self.loading.contains_key(id) {
+            self.track_source(id, &img);
+            let size = img.gpu_size();
+            let (f, sender) = GpuLoadFuture::batched(
+                img.clone(),
+                self.device.clone(),
+                self.queue.clone(),
+                self.pool.clone(),
+            );
+            let waker = f.waker();
+            let duration = f.upload_duration();
+            let mut f = f.shared();
+            futures::poll!(&mut f);
+            self.batch.push(PendingUpload {
+                id: id.clone(),
+                asset: img,
+                duration: duration.clone(),
+                sender,
+                waker,
+            });
+            self.flush_batch = Some(flush_images);
+            self.loading.insert(id.clone(), (f, size, duration));
+            if self.batch.len() >= self.batch_limit {
+                self.flush();
+            }
+        }
+    }
+}
+
+/// Uploads queued images through one staging buffer and a single command submission.
+fn flush_images(
+    device: &Device,
+    queue: &Queue,
+    batch: Vec<PendingUpload<ImageData>>,
+) -> BatchStats {
+    let start = Instant::now();
+    let plan = StagingPlan::new(
+        batch
+            .iter()
+            .map(|upload| (upload.asset.bytes_per_row(), upload.asset.extent().height)),
+    );
+    let sources = batch
+        .iter()
+        .map(|upload| upload.asset.raw())
+        .collect::<Vec<_>>();
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("staging"),
+        size: plan.size(),
+        usage: BufferUsage::COPY_SRC | BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    });
+    queue.write_buffer(&staging, 0, &plan.write(&sources));
+
+    let mut encoder =
+        device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    let textures = batch
+        .iter()
+        .zip(plan.regions())
+        .map(|(upload, region)| {
+            let texture = upload.asset.create_texture(device);
+            encoder.copy_buffer_to_texture(
+                wgpu::BufferCopyView {
+                    buffer: &staging,
+                    layout: wgpu::TextureDataLayout {
+                        offset: region.offset,
+                        bytes_per_row: region.bytes_per_row,
+                        rows_per_image: region.rows,
+                    },
+                },
+                wgpu::TextureCopyView {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                upload.asset.extent(),
+            );
+            texture
+        })
+        .collect::<Vec<_>>();
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let duration = start.elapsed();
+    for (upload, texture) in batch.into_iter().zip(textures) {
+        *upload.duration.lock().unwrap() = Some(duration);
+        // The future may have been dropped already, nothing to forward to then.
+        let _ = upload.sender.send(Arc::new(texture));
+        upload.waker.wake();
+    }
+    plan.stats()
 }
 
 #[cfg(test)]
 mod tests {
-    use super::AsyncGpuManager;
-    use crate::{imagedata::ImageData, AsyncFileManager, LoadStatus};
+    use super::{estimate_texture_size, AsyncGpuManager};
+    use crate::{
+        imagedata::ImageData, AsyncFileManager, FileLoadFuture, GpuAsset, Identifier, LoadStatus,
+    };
     use futures::executor::ThreadPoolBuilder;
     use futures::FutureExt;
-    use std::{path::PathBuf, sync::Arc};
+    use std::{
+        path::{Path, PathBuf},
+        sync::Arc,
+        time::Duration,
+    };
+    use wgpu::{Device, Queue};
+
+    /// Uploads its version as the resource, panics on the worker if it is broken.
+    struct Versioned {
+        path: PathBuf,
+        version: u32,
+        broken: bool,
+    }
+
+    impl GpuAsset for Versioned {
+        type Resource = u32;
+        fn gpu_size(&self) -> u64 {
+            4
+        }
+        fn upload(&self, _device: &Device, _queue: &Queue) -> u32 {
+            assert!(!self.broken, "broken asset");
+            self.version
+        }
+        fn source(&self) -> Option<&Path> {
+            Some(&self.path)
+        }
+    }
+
+    async fn device() -> (Arc<Device>, Arc<Queue>) {
+        let (needed_features, unsafe_features) =
+            (wgpu::Features::empty(), wgpu::UnsafeFeatures::disallow());
+
+        let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+        let adapter = instance
+            .request_adapter(
+                &wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::Default,
+                    compatible_surface: None,
+                },
+                unsafe_features,
+            )
+            .await
+            .unwrap();
+
+        let adapter_features = adapter.features();
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    features: adapter_features & needed_features,
+                    limits: wgpu::Limits::default(),
+                    shader_validation: true,
+                },
+                None,
+            )
+            .await
+            .unwrap();
+        (Arc::new(device), Arc::new(queue))
+    }
+    #[test]
+    fn texture_size() {
+        let extent = wgpu::Extent3d {
+            width: 256,
+            height: 128,
+            depth: 1,
+        };
+        assert_eq!(
+            estimate_texture_size(extent, wgpu::TextureFormat::Rgba8Unorm, 1),
+            256 * 128 * 4
+        );
+        assert_eq!(
+            estimate_texture_size(extent, wgpu::TextureFormat::Rgba32Float, 1),
+            256 * 128 * 16
+        );
+        assert_eq!(
+            estimate_texture_size(extent, wgpu::TextureFormat::R8Unorm, 3),
+            256 * 128 + 128 * 64 + 64 * 32
+        );
+    }
     #[test]
     fn manager() {
         async_std::task::block_on(async {
-            let (needed_features, unsafe_features) =
-                (wgpu::Features::empty(), wgpu::UnsafeFeatures::disallow());
-
-            let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
-            let adapter = instance
-                .request_adapter(
-                    &wgpu::RequestAdapterOptions {
-                        power_preference: wgpu::PowerPreference::Default,
-                        compatible_surface: None,
-                    },
-                    unsafe_features,
-                )
-                .await
-                .unwrap();
-
-            let adapter_features = adapter.features();
-            let (device, queue) = adapter
-                .request_device(
-                    &wgpu::DeviceDescriptor {
-                        features: adapter_features & needed_features,
-                        limits: wgpu::Limits::default(),
-                        shader_validation: true,
-                    },
-                    None,
-                )
-                .await
-                .unwrap();
-            let arc_device = Arc::new(device);
-            let arc_queue = Arc::new(queue);
+            let (arc_device, arc_queue) = device().await;
 
             let pool = Arc::new(ThreadPoolBuilder::new().create().unwrap());
             let path = PathBuf::new().join("small_scream.png");
             let id = path.clone().into();
             let mut imgmngr = AsyncFileManager::<ImageData>::new(pool.clone());
 
-            let mut gpumngr = AsyncGpuManager::new(pool, arc_device, arc_queue);
+            let mut gpumngr = AsyncGpuManager::new(pool.clone(), arc_device, arc_queue);
 
             imgmngr.load(&path).await;
             match imgmngr.get(&path).await {
@@ -119,11 +633,100 @@ mod tests {
                 LoadStatus::Loaded(img) => gpumngr.load(&id, img).await,
                 _ => panic!(),
             };
-            let _texture = match gpumngr.get(&id).await {
+            let texture = match gpumngr.get(&id).await {
                 LoadStatus::Loading(fut) => fut.await.unwrap(),
                 LoadStatus::Loaded(tex) => tex,
                 _ => panic!(),
             };
+            assert_eq!(gpumngr.generation(&id), Some(0));
+
+            let reloaded = FileLoadFuture::<ImageData>::new(&path, pool).await.unwrap();
+            assert_eq!(gpumngr.reload(reloaded).await, Some(id.clone()));
+            loop {
+                match gpumngr.get(&id).await {
+                    LoadStatus::Loaded(tex) if Arc::ptr_eq(&tex, &texture) => {
+                        async_std::task::yield_now().await
+                    }
+                    LoadStatus::Loaded(_) => break,
+                    _ => panic!(),
+                }
+            }
+            assert_eq!(gpumngr.generation(&id), Some(1));
+        });
+    }
+    #[test]
+    fn remove_while_reloading() {
+        async_std::task::block_on(async {
+            let (device, queue) = device().await;
+            let pool = Arc::new(ThreadPoolBuilder::new().create().unwrap());
+            let path = PathBuf::new().join("small_scream.png");
+            let id = Identifier::from(path.clone());
+            let img = FileLoadFuture::<ImageData>::new(&path, pool.clone())
+                .await
+                .unwrap();
+
+            let mut gpumngr = AsyncGpuManager::new(pool, device, queue);
+            gpumngr.load(&id, img.clone()).await;
+            if let LoadStatus::Loading(f) = gpumngr.get(&id).await {
+                f.await.unwrap();
+            }
+            assert!(matches!(gpumngr.get(&id).await, LoadStatus::Loaded(_)));
+            assert_eq!(gpumngr.reload(img.clone()).await, Some(id.clone()));
+            assert_eq!(gpumngr.remove(&id), vec![id.clone()]);
+
+            // The dropped reload must not bring the texture back once it is uploaded.
+            async_std::task::sleep(Duration::from_millis(100)).await;
+            assert!(matches!(gpumngr.get(&id).await, LoadStatus::NotLoading));
+            assert_eq!(gpumngr.usage(), 0);
+            assert_eq!(gpumngr.generation(&id), None);
+            assert_eq!(gpumngr.reload(img).await, None);
+        });
+    }
+    #[test]
+    fn failed_reload() {
+        async_std::task::block_on(async {
+            let (device, queue) = device().await;
+            let pool = Arc::new(ThreadPoolBuilder::new().pool_size(2).create().unwrap());
+            let id = Identifier::from(PathBuf::from("versioned"));
+            let asset = |version, broken| {
+                Arc::new(Versioned {
+                    path: PathBuf::from("versioned"),
+                    version,
+                    broken,
+                })
+            };
+
+            let mut gpumngr = AsyncGpuManager::new(pool, device, queue);
+            gpumngr.load(&id, asset(1, false)).await;
+            if let LoadStatus::Loading(f) = gpumngr.get(&id).await {
+                f.await.unwrap();
+            }
+            assert!(matches!(gpumngr.get(&id).await, LoadStatus::Loaded(_)));
+            assert_eq!(gpumngr.reload(asset(2, true)).await, Some(id.clone()));
+            while gpumngr.last_error(&id).is_none() {
+                match gpumngr.get(&id).await {
+                    LoadStatus::Loaded(version) => assert_eq!(*version, 1),
+                    _ => panic!(),
+                }
+                async_std::task::yield_now().await;
+            }
+            // The previous resource is still served after the reload failed.
+            match gpumngr.get(&id).await {
+                LoadStatus::Loaded(version) => assert_eq!(*version, 1),
+                _ => panic!(),
+            }
+
+            assert_eq!(gpumngr.reload(asset(3, false)).await, Some(id.clone()));
+            loop {
+                match gpumngr.get(&id).await {
+                    LoadStatus::Loaded(version) if *version == 1 => {
+                        async_std::task::yield_now().await
+                    }
+                    LoadStatus::Loaded(version) => break assert_eq!(*version, 3),
+                    _ => panic!(),
+                }
+            }
+            assert!(gpumngr.last_error(&id).is_none());
         });
     }
 }
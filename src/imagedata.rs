@@ -1,7 +1,13 @@
+use crate::{assetref::Dependencies, gpuasset::GpuAsset, gpumanager::estimate_texture_size};
 use image::ImageFormat;
-use std::{convert::TryFrom, path::PathBuf, sync::Arc};
+use std::{
+    convert::TryFrom,
+    path::{Path, PathBuf},
+};
 use wgpu::{Device, Queue, Texture};
 
+const MIP_LEVEL_COUNT: u32 = 1;
+
 fn convert_format(i: ImageFormat) -> wgpu::TextureFormat {
     match i {
         ImageFormat::Hdr => wgpu::TextureFormat::Rgba32Float,
@@ -12,24 +18,20 @@ fn convert_format(i: ImageFormat) -> wgpu::TextureFormat {
 
 #[derive(Debug, PartialEq)]
 pub struct ImageData {
+    path: PathBuf,
     name: Option<String>,
     extent: wgpu::Extent3d,
     raw: Vec<u8>,
     format: ImageFormat,
 }
 
-impl ImageData {
-    pub fn upload(&self, device: Arc<Device>, queue: Arc<Queue>) -> Texture {
-        let format = convert_format(self.format);
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
-            size: self.extent,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format,
-            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
-            label: None,
-        });
+impl GpuAsset for ImageData {
+    type Resource = Texture;
+    fn gpu_size(&self) -> u64 {
+        estimate_texture_size(self.extent, convert_format(self.format), MIP_LEVEL_COUNT)
+    }
+    fn upload(&self, device: &Device, queue: &Queue) -> Texture {
+        let texture = self.create_texture(device);
         queue.write_texture(
             wgpu::TextureCopyView {
                 texture: &texture,
@@ -39,15 +41,44 @@ impl ImageData {
             &self.raw[..],
             wgpu::TextureDataLayout {
                 offset: 0,
-                bytes_per_row: (self.raw.len() as f64 / self.extent.height as f64) as u32,
+                bytes_per_row: self.bytes_per_row(),
                 rows_per_image: self.extent.height,
             },
             self.extent,
         );
         texture
     }
+    fn source(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
 }
 
+impl ImageData {
+    pub(crate) fn create_texture(&self, device: &Device) -> Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            size: self.extent,
+            mip_level_count: MIP_LEVEL_COUNT,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: convert_format(self.format),
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+            label: None,
+        })
+    }
+    pub(crate) fn extent(&self) -> wgpu::Extent3d {
+        self.extent
+    }
+    pub(crate) fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+    /// Unpadded length of one row of texels.
+    pub(crate) fn bytes_per_row(&self) -> u32 {
+        (self.raw.len() as f64 / self.extent.height as f64) as u32
+    }
+}
+
+impl Dependencies for ImageData {}
+
 impl TryFrom<(PathBuf, Vec<u8>)> for ImageData {
     fn try_from((p, raw): (PathBuf, Vec<u8>)) -> Result<Self, std::io::Error> {
         if let Some(format) = get_format_from_extension(&p) {
@@ -67,6 +98,7 @@ impl TryFrom<(PathBuf, Vec<u8>)> for ImageData {
                 },
                 raw: image.into_raw(),
                 format,
+                path: p,
             })
         } else {
             todo!() // TODO: Maybe guess format from raw?
@@ -1,15 +1,56 @@
+mod assetref;
+mod bufferdata;
+mod configmanager;
+mod dependencygraph;
 mod fileloader;
 mod filemanager;
+mod format;
+mod gpuasset;
 mod gpuloader;
 mod gpumanager;
 mod imagedata;
+mod memorysource;
+mod pack;
+mod staging;
+mod streamdecode;
+mod textureloader;
+mod texturemanager;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+mod validation;
+mod vfs;
+mod zipsource;
 
-mod ronmanager;
+mod source;
 
-pub use fileloader::FileLoadFuture;
+pub use assetref::{AssetRef, Dependencies, Dependency, Registry};
+pub use bufferdata::BufferData;
+#[allow(deprecated)]
+pub use configmanager::{
+    AnyConfig, AnyConfigLoadFuture, AnyRon, AnyRonLoadFuture, Config, ConfigFile, ConfigLoadFuture,
+    ConfigManager, ConfigManagerError, Ron, RonFile, RonLoadFuture, RonManager, RonManagerError,
+};
+pub use dependencygraph::{DependencyGraph, NodeInfo, NodeStatus, SharedGraph};
+pub use fileloader::{FileLoadFuture, LoadStats};
 pub use filemanager::AsyncFileManager;
+pub use format::{Format, FormatError};
 use futures::{future::Shared, Future};
+pub use gpuasset::GpuAsset;
+pub use gpumanager::AsyncGpuManager;
+pub use imagedata::ImageData;
+pub use memorysource::MemorySource;
+pub use pack::{Compression, Pack, PackEntry, PackWriter};
+pub use source::{FileBytes, FileSystem, Metadata, OpenFile, ReadMode, Source, Watch};
+pub use staging::{BatchStats, StagingPlan, StagingRegion};
 use std::{io::Error, path::PathBuf, sync::Arc};
+pub use streamdecode::{ChunkReader, DecodeHeader, Header, LoadProgress, StreamDecode};
+pub use textureloader::{LoadedTexture, TextureLoadFuture};
+pub use texturemanager::AsyncTextureManager;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub use uring::UringReader;
+pub use validation::{Problem, Schema, ValidationReport};
+pub use vfs::Vfs;
+pub use zipsource::ZipSource;
 
 ///
 pub enum LoadStatus<T, F>
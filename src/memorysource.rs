@@ -0,0 +1,264 @@
+use crate::{source::OnChange, Metadata, Source, Watch};
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashMap},
+    io::{Error, ErrorKind},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::SystemTime,
+};
+
+struct MemoryFile {
+    bytes: Cow<'static, [u8]>,
+    modified: SystemTime,
+}
+
+type Watcher = (PathBuf, OnChange, Arc<AtomicBool>);
+
+/// Files kept in memory, e.g. embedded with `include_bytes!` or generated at runtime.
+///
+/// Files can be added and removed while the source is in use, watchers of a path are
+/// called on every change of it. Mounted in a `Vfs` above the disk it overrides files there.
+#[derive(Default)]
+pub struct MemorySource {
+    files: RwLock<HashMap<PathBuf, MemoryFile>>,
+    watchers: Mutex<Vec<Watcher>>,
+}
+
+/// `path` without `.` components, so lookups match however the path is written.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Adds a file, e.g. `with_file("shaders/sky.wgsl", &include_bytes!("sky.wgsl")[..])`.
+    pub fn with_file<P, B>(self, path: P, bytes: B) -> Self
+    where
+        P: AsRef<Path>,
+        B: Into<Cow<'static, [u8]>>,
+    {
+        self.insert(path, bytes);
+        self
+    }
+    /// Adds or replaces a file.
+    pub fn insert<P, B>(&self, path: P, bytes: B)
+    where
+        P: AsRef<Path>,
+        B: Into<Cow<'static, [u8]>>,
+    {
+        let path = normalize(path.as_ref());
+        self.files.write().unwrap().insert(
+            path.clone(),
+            MemoryFile {
+                bytes: bytes.into(),
+                modified: SystemTime::now(),
+            },
+        );
+        self.notify(&path);
+    }
+    /// Removes a file, returns whether it existed.
+    pub fn remove<P: AsRef<Path>>(&self, path: P) -> bool {
+        let path = normalize(path.as_ref());
+        let removed = self.files.write().unwrap().remove(&path).is_some();
+        if removed {
+            self.notify(&path);
+        }
+        removed
+    }
+    /// Calls the watchers of `path` once the list is unlocked, so they may watch, insert
+    /// or remove files themselves.
+    fn notify(&self, path: &Path) {
+        let on_change = {
+            let mut watchers = self.watchers.lock().unwrap();
+            watchers.retain(|(_, _, stopped)| !stopped.load(Ordering::Relaxed));
+            watchers
+                .iter()
+                .filter(|(watched, ..)| watched == path)
+                .map(|(_, on_change, _)| on_change.clone())
+                .collect::<Vec<_>>()
+        };
+        for on_change in on_change {
+            (on_change.lock().unwrap())(path);
+        }
+    }
+    fn not_found(path: &Path) -> Error {
+        Error::new(
+            ErrorKind::NotFound,
+            format!("{} not found in memory", path.display()),
+        )
+    }
+    /// Names of the entries directly in `directory`, `None` if it does not exist.
+    fn children(&self, directory: &Path) -> Option<BTreeSet<PathBuf>> {
+        let files = self.files.read().unwrap();
+        let children = files
+            .keys()
+            .filter_map(|path| path.strip_prefix(directory).ok())
+            .filter_map(|rest| rest.components().next())
+            .map(|name| PathBuf::from(name.as_os_str()))
+            .collect::<BTreeSet<_>>();
+        if children.is_empty() && directory != Path::new("") {
+            None
+        } else {
+            Some(children)
+        }
+    }
+}
+
+impl Source for MemorySource {
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        self.files
+            .read()
+            .unwrap()
+            .get(&normalize(path))
+            .map(|file| file.bytes.to_vec())
+            .ok_or_else(|| MemorySource::not_found(path))
+    }
+    fn metadata(&self, path: &Path) -> Result<Metadata, Error> {
+        let normalized = normalize(path);
+        if let Some(file) = self.files.read().unwrap().get(&normalized) {
+            return Ok(Metadata {
+                len: file.bytes.len() as u64,
+                modified: Some(file.modified),
+                is_dir: false,
+            });
+        }
+        match self.children(&normalized) {
+            Some(_) => Ok(Metadata {
+                len: 0,
+                modified: None,
+                is_dir: true,
+            }),
+            None => Err(MemorySource::not_found(path)),
+        }
+    }
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+        let children = self
+            .children(&normalize(path))
+            .ok_or_else(|| MemorySource::not_found(path))?;
+        Ok(children.into_iter().map(|name| path.join(name)).collect())
+    }
+    /// Calls `on_change` whenever the file is inserted or removed.
+    fn watch(&self, path: &Path, on_change: Box<dyn Fn(&Path) + Send>) -> Result<Watch, Error> {
+        let stopped = Arc::new(AtomicBool::new(false));
+        self.watchers.lock().unwrap().push((
+            normalize(path),
+            Arc::new(Mutex::new(on_change)),
+            stopped.clone(),
+        ));
+        Ok(Watch::new(stopped))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemorySource;
+    use crate::{AsyncFileManager, FileSystem, LoadStatus, Source, Vfs};
+    use futures::executor::ThreadPool;
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        sync::{mpsc::channel, Arc},
+    };
+
+    #[test]
+    fn memory() {
+        let source = MemorySource::new()
+            .with_file(
+                "material.ron",
+                &include_bytes!("../testfiles/material.ron")[..],
+            )
+            .with_file("./textures/a.png", vec![1, 2, 3]);
+        assert_eq!(
+            source.read(Path::new("material.ron")).unwrap(),
+            fs::read("testfiles/material.ron").unwrap()
+        );
+        assert_eq!(
+            source
+                .read_range(Path::new("textures/a.png"), 1..5)
+                .unwrap(),
+            vec![2, 3]
+        );
+        assert!(source.metadata(Path::new("textures")).unwrap().is_dir);
+        assert_eq!(
+            source.read_dir(Path::new("")).unwrap(),
+            vec![PathBuf::from("material.ron"), PathBuf::from("textures")]
+        );
+
+        let (tx, rx) = channel();
+        let watch = source
+            .watch(
+                Path::new("textures/a.png"),
+                Box::new(move |path| tx.send(path.to_owned()).unwrap()),
+            )
+            .unwrap();
+        source.insert("textures/a.png", vec![4]);
+        assert_eq!(rx.try_recv().unwrap(), PathBuf::from("textures/a.png"));
+        assert!(source.remove("textures/a.png"));
+        assert!(rx.try_recv().is_ok());
+        assert!(source.read(Path::new("textures/a.png")).is_err());
+        drop(watch);
+        source.insert("textures/a.png", vec![5]);
+        assert!(rx.try_recv().is_err());
+
+        // Watchers may change the source themselves.
+        let source = Arc::new(source);
+        let inner = Arc::downgrade(&source);
+        let _watch = source
+            .watch(
+                Path::new("a"),
+                Box::new(move |_| inner.upgrade().unwrap().insert("b", vec![1])),
+            )
+            .unwrap();
+        source.insert("a", vec![0]);
+        assert_eq!(source.read(Path::new("b")).unwrap(), vec![1]);
+    }
+
+    #[derive(Debug)]
+    struct LoadedFile {
+        bytes: Vec<u8>,
+    }
+
+    impl From<(PathBuf, Vec<u8>)> for LoadedFile {
+        fn from((_path, bytes): (PathBuf, Vec<u8>)) -> Self {
+            LoadedFile { bytes }
+        }
+    }
+
+    #[test]
+    fn layer() {
+        let memory = Arc::new(MemorySource::new().with_file("material.ron", &b"(patched)"[..]));
+        let vfs = Vfs::new()
+            .mount("disk", "", 0, Arc::new(FileSystem::new("testfiles")))
+            .mount("memory", "", 1, memory.clone());
+        let pool = Arc::new(ThreadPool::new().unwrap());
+        let mut manager = AsyncFileManager::<LoadedFile>::with_source(pool, Arc::new(vfs));
+        futures::executor::block_on(async {
+            for (path, layer) in &[("material.ron", "memory"), ("base.ron", "disk")] {
+                manager.load(path).await;
+                if let LoadStatus::Loading(f) = manager.get(path).await {
+                    f.await.unwrap();
+                }
+                assert_eq!(manager.layer(path).as_deref(), Some(*layer));
+            }
+            match manager.get("material.ron").await {
+                LoadStatus::Loaded(file) => assert_eq!(file.bytes, b"(patched)"),
+                _ => panic!(),
+            }
+            // Files added at runtime are served once reloaded.
+            memory.insert("base.ron", &b"(added)"[..]);
+            manager.reload("base.ron").await;
+            if let LoadStatus::Loading(f) = manager.get("base.ron").await {
+                f.await.unwrap();
+            }
+            assert_eq!(manager.layer("base.ron").as_deref(), Some("memory"));
+        });
+    }
+}
@@ -0,0 +1,630 @@
+use crate::{source::ConcurrentFile, Metadata, Source};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    convert::{TryFrom, TryInto},
+    fs::{self, File},
+    io::{BufWriter, Error, ErrorKind, Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+const MAGIC: &[u8; 4] = b"AFPK";
+const VERSION: u32 = 1;
+const HEADER_SIZE: u64 = 32;
+const ENTRY_SIZE: u64 = 48;
+/// Default alignment of blobs in the file, see `PackWriter::with_alignment`.
+const DEFAULT_ALIGNMENT: u32 = 16;
+
+/// How a file is stored in a pack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Deflate,
+}
+
+impl Compression {
+    fn from_u8(value: u8) -> Result<Self, Error> {
+        match value {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Deflate),
+            _ => Err(invalid(format!("unknown compression {}", value))),
+        }
+    }
+    fn to_u8(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Deflate => 1,
+        }
+    }
+}
+
+/// A file in a pack as listed in its index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackEntry {
+    pub path: PathBuf,
+    /// Size of the file once decompressed.
+    pub size: u64,
+    /// Size of the blob in the pack.
+    pub stored_size: u64,
+    pub compression: Compression,
+    /// CRC-32 of the decompressed file.
+    pub checksum: u32,
+    offset: u64,
+    name: String,
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn corrupt(what: &str) -> Error {
+    invalid(format!("corrupt pack {}", what))
+}
+
+/// A size from the pack as `usize`, failing on targets where it does not fit.
+fn to_usize(value: u64) -> Result<usize, Error> {
+    usize::try_from(value).map_err(|_| corrupt("size"))
+}
+
+/// A count or length written to the index as `u32`, failing if the pack would need more.
+fn to_u32(value: u64, what: &str) -> Result<u32, Error> {
+    u32::try_from(value).map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("too many {} for a pack: {}", what, value),
+        )
+    })
+}
+
+/// 64 bit FNV-1a hash of a path as written in the index.
+fn hash(name: &str) -> u64 {
+    name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// `path` as written in the index, `/` separated and without `.` components.
+fn name(path: &Path) -> Result<String, Error> {
+    let mut names = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => names.push(
+                name.to_str()
+                    .ok_or_else(|| invalid(format!("{} is not UTF-8", path.display())))?,
+            ),
+            Component::CurDir => {}
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} is not a relative path", path.display()),
+                ))
+            }
+        }
+    }
+    Ok(names.join("/"))
+}
+
+/// Rounds `offset` up to a multiple of `alignment`, a power of two.
+fn align(offset: u64, alignment: u64) -> u64 {
+    (offset + alignment - 1) & !(alignment - 1)
+}
+
+/// Reads the little endian integer at `offset` of `bytes`.
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// A pack file, opened by reading its header and index only.
+///
+/// Layout, all integers little endian:
+/// - header: magic `AFPK`, version u32, entry count u32, alignment u32,
+///   offset and length of the name table u64
+/// - index: per entry path hash u64, blob offset u64, stored size u64, size u64,
+///   CRC-32 u32, compression u8, 3 bytes padding, name offset u32, name length u32,
+///   sorted by hash
+/// - name table: the UTF-8 paths of all entries, `/` separated
+/// - blobs, each starting at a multiple of the alignment
+pub struct Pack {
+    path: PathBuf,
+    file: ConcurrentFile,
+    modified: Option<SystemTime>,
+    hashes: Vec<u64>,
+    entries: Vec<PackEntry>,
+}
+
+impl Pack {
+    /// Fails with `InvalidData` if the header or index point past the end of the file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = File::open(path.as_ref())?;
+        let metadata = file.metadata()?;
+        let (len, modified) = (metadata.len(), metadata.modified().ok());
+        let file = ConcurrentFile::new(file);
+        let mut header = [0; HEADER_SIZE as usize];
+        file.read_exact_at(&mut header, 0)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid(format!(
+                "{} is not a pack",
+                path.as_ref().display()
+            )));
+        }
+        let version = read_u32(&header, 4);
+        if version != VERSION {
+            return Err(invalid(format!("unsupported pack version {}", version)));
+        }
+        let count = u64::from(read_u32(&header, 8));
+        let names_offset = read_u64(&header, 16);
+        let names_len = read_u64(&header, 24);
+        let index_len = count * ENTRY_SIZE;
+        let index_end = names_offset.checked_add(names_len);
+        if names_offset != HEADER_SIZE + index_len || !matches!(index_end, Some(end) if end <= len)
+        {
+            return Err(corrupt("header"));
+        }
+        // Index and names are read at once, entries are not touched until they are read.
+        let mut index = vec![0; to_usize(index_len + names_len)?];
+        file.read_exact_at(&mut index, HEADER_SIZE)?;
+        let (index, names) = index.split_at(to_usize(index_len)?);
+        let mut hashes = Vec::with_capacity(to_usize(count)?);
+        let mut entries = Vec::with_capacity(to_usize(count)?);
+        for bytes in index.chunks(ENTRY_SIZE as usize) {
+            let name_offset = read_u32(bytes, 40) as usize;
+            let name = name_offset
+                .checked_add(read_u32(bytes, 44) as usize)
+                .and_then(|name_end| names.get(name_offset..name_end))
+                .and_then(|name| std::str::from_utf8(name).ok())
+                .ok_or_else(|| corrupt("name table"))?;
+            let entry = PackEntry {
+                path: PathBuf::from(name),
+                offset: read_u64(bytes, 8),
+                stored_size: read_u64(bytes, 16),
+                size: read_u64(bytes, 24),
+                checksum: read_u32(bytes, 32),
+                compression: Compression::from_u8(bytes[36])?,
+                name: name.to_owned(),
+            };
+            let blob_end = entry.offset.checked_add(entry.stored_size);
+            if !matches!(blob_end, Some(end) if end <= len)
+                || (entry.compression == Compression::None && entry.stored_size != entry.size)
+            {
+                return Err(corrupt(&format!("entry {}", name)));
+            }
+            hashes.push(read_u64(bytes, 0));
+            entries.push(entry);
+        }
+        if hashes.windows(2).any(|pair| pair[0] > pair[1]) {
+            return Err(invalid(String::from("pack index is not sorted")));
+        }
+        Ok(Self {
+            path: path.as_ref().to_owned(),
+            file,
+            modified,
+            hashes,
+            entries,
+        })
+    }
+    /// Path of the pack on disk.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Every file in the pack, in index order.
+    pub fn entries(&self) -> &[PackEntry] {
+        &self.entries
+    }
+    /// The entry of a file, found by binary search on the hash of its path.
+    pub fn entry<P: AsRef<Path>>(&self, path: P) -> Option<&PackEntry> {
+        let name = name(path.as_ref()).ok()?;
+        let hash = hash(&name);
+        let start = self.hashes.partition_point(|&h| h < hash);
+        self.hashes[start..]
+            .iter()
+            .take_while(|&&h| h == hash)
+            .zip(&self.entries[start..])
+            .map(|(_, entry)| entry)
+            .find(|entry| entry.name == name)
+    }
+    /// Reads and decompresses the file, failing with `InvalidData` if its checksum is wrong.
+    pub fn read_entry(&self, entry: &PackEntry) -> Result<Vec<u8>, Error> {
+        let mut stored = vec![0; to_usize(entry.stored_size)?];
+        self.file.read_exact_at(&mut stored, entry.offset)?;
+        let bytes = match entry.compression {
+            Compression::None => stored,
+            Compression::Deflate => {
+                // One byte past the size tells a longer file, without inflating all of it.
+                let mut bytes = Vec::new();
+                DeflateDecoder::new(&stored[..])
+                    .take(entry.size.saturating_add(1))
+                    .read_to_end(&mut bytes)
+                    .map_err(|e| invalid(format!("{}: {}", entry.path.display(), e)))?;
+                bytes
+            }
+        };
+        if bytes.len() as u64 != entry.size || crc32fast::hash(&bytes) != entry.checksum {
+            return Err(invalid(format!(
+                "{}: checksum mismatch in {}",
+                entry.path.display(),
+                self.path.display()
+            )));
+        }
+        Ok(bytes)
+    }
+    fn find(&self, path: &Path) -> Result<&PackEntry, Error> {
+        self.entry(path).ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("{} not found in {}", path.display(), self.path.display()),
+            )
+        })
+    }
+    /// Names of the entries directly in the directory `name`, subdirectories once each.
+    fn children(&self, name: &str) -> BTreeSet<&str> {
+        let prefix = if name.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", name)
+        };
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.name.strip_prefix(prefix.as_str()))
+            .filter_map(|rest| rest.split('/').next())
+            .collect()
+    }
+}
+
+impl Source for Pack {
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        self.read_entry(self.find(path)?)
+    }
+    /// Reads only `range` of uncompressed files, their checksum is not verified then.
+    fn read_range(&self, path: &Path, range: Range<u64>) -> Result<Vec<u8>, Error> {
+        let entry = self.find(path)?;
+        let start = range.start.min(entry.size);
+        let end = range.end.max(start).min(entry.size);
+        match entry.compression {
+            Compression::None => {
+                let mut bytes = vec![0; to_usize(end - start)?];
+                self.file.read_exact_at(&mut bytes, entry.offset + start)?;
+                Ok(bytes)
+            }
+            Compression::Deflate => {
+                Ok(self.read_entry(entry)?[start as usize..end as usize].to_vec())
+            }
+        }
+    }
+    fn metadata(&self, path: &Path) -> Result<Metadata, Error> {
+        if let Some(entry) = self.entry(path) {
+            return Ok(Metadata {
+                len: entry.size,
+                modified: self.modified,
+                is_dir: false,
+            });
+        }
+        let name = name(path)?;
+        if name.is_empty() || !self.children(&name).is_empty() {
+            return Ok(Metadata {
+                len: 0,
+                modified: self.modified,
+                is_dir: true,
+            });
+        }
+        Err(self.find(path).unwrap_err())
+    }
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+        let name = name(path)?;
+        let children = self.children(&name);
+        if children.is_empty() && !name.is_empty() {
+            return Err(self.find(path).unwrap_err());
+        }
+        Ok(children.into_iter().map(|child| path.join(child)).collect())
+    }
+}
+
+/// A file added to a `PackWriter`, files on disk are only read while the pack is written.
+enum Input {
+    Bytes(Vec<u8>),
+    File(PathBuf),
+}
+
+/// Builds a pack from files in memory or on disk.
+pub struct PackWriter {
+    alignment: u32,
+    files: BTreeMap<String, (Input, Compression)>,
+}
+
+impl Default for PackWriter {
+    fn default() -> Self {
+        Self {
+            alignment: DEFAULT_ALIGNMENT,
+            files: BTreeMap::new(),
+        }
+    }
+}
+
+impl PackWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Starts every blob at a multiple of `alignment` bytes, which must be a power of two.
+    pub fn with_alignment(mut self, alignment: u32) -> Self {
+        assert!(
+            alignment.is_power_of_two(),
+            "Alignment must be a power of two!"
+        );
+        self.alignment = alignment;
+        self
+    }
+    /// Adds a file, replacing an earlier one with the same path.
+    /// Files that do not get smaller with `compression` are stored uncompressed.
+    pub fn add<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        bytes: Vec<u8>,
+        compression: Compression,
+    ) -> Result<(), Error> {
+        self.files
+            .insert(name(path.as_ref())?, (Input::Bytes(bytes), compression));
+        Ok(())
+    }
+    /// Adds every file below `dir`, with paths relative to it.
+    /// The files are read one at a time once the pack is written.
+    pub fn add_dir<P: AsRef<Path>>(
+        &mut self,
+        dir: P,
+        compression: Compression,
+    ) -> Result<(), Error> {
+        let mut pending = vec![PathBuf::new()];
+        while let Some(relative) = pending.pop() {
+            for entry in fs::read_dir(dir.as_ref().join(&relative))? {
+                let entry = entry?;
+                let path = relative.join(entry.file_name());
+                if entry.file_type()?.is_dir() {
+                    pending.push(path);
+                } else {
+                    self.files
+                        .insert(name(&path)?, (Input::File(entry.path()), compression));
+                }
+            }
+        }
+        Ok(())
+    }
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+    /// Writes the pack from the current position of `writer`. Files are read, compressed and
+    /// written one at a time, the index is filled in once the size of every blob is known.
+    pub fn write_to<W: Write + Seek>(&self, writer: &mut W) -> Result<(), Error> {
+        let alignment = u64::from(self.alignment);
+        let start = writer.stream_position()?;
+        let mut names = self
+            .files
+            .keys()
+            .map(|name| (hash(name), name))
+            .collect::<Vec<_>>();
+        names.sort();
+
+        let count = names.len() as u64;
+        let names_offset = HEADER_SIZE + count * ENTRY_SIZE;
+        let names_len = names.iter().map(|(_, name)| name.len() as u64).sum::<u64>();
+        let count_u32 = to_u32(count, "entries")?;
+        // Every name offset and length is within this, so they fit as well.
+        to_u32(names_len, "bytes of names")?;
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&count_u32.to_le_bytes());
+        header.extend_from_slice(&self.alignment.to_le_bytes());
+        header.extend_from_slice(&names_offset.to_le_bytes());
+        header.extend_from_slice(&names_len.to_le_bytes());
+        writer.write_all(&header)?;
+        // The index is written over this once the blobs are.
+        writer.write_all(&vec![0; (count * ENTRY_SIZE) as usize])?;
+        for (_, name) in &names {
+            writer.write_all(name.as_bytes())?;
+        }
+
+        let mut index = Vec::with_capacity((count * ENTRY_SIZE) as usize);
+        let mut name_offset = 0u32;
+        let mut position = names_offset + names_len;
+        for (hash, name) in &names {
+            let (input, compression) = &self.files[*name];
+            let bytes = match input {
+                Input::Bytes(bytes) => Cow::Borrowed(bytes.as_slice()),
+                Input::File(path) => Cow::Owned(fs::read(path)?),
+            };
+            let compressed = match compression {
+                Compression::None => None,
+                Compression::Deflate => {
+                    let mut encoder =
+                        DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                    encoder.write_all(&bytes)?;
+                    Some(encoder.finish()?).filter(|compressed| compressed.len() < bytes.len())
+                }
+            };
+            let (stored, compression) = match &compressed {
+                Some(compressed) => (compressed.as_slice(), Compression::Deflate),
+                None => (&bytes[..], Compression::None),
+            };
+            let offset = align(position, alignment);
+            writer.write_all(&vec![0; (offset - position) as usize])?;
+            writer.write_all(stored)?;
+            position = offset + stored.len() as u64;
+
+            index.extend_from_slice(&hash.to_le_bytes());
+            index.extend_from_slice(&offset.to_le_bytes());
+            index.extend_from_slice(&(stored.len() as u64).to_le_bytes());
+            index.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            index.extend_from_slice(&crc32fast::hash(&bytes).to_le_bytes());
+            index.extend_from_slice(&[compression.to_u8(), 0, 0, 0]);
+            index.extend_from_slice(&name_offset.to_le_bytes());
+            index.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            name_offset += name.len() as u32;
+        }
+        writer.seek(SeekFrom::Start(start + HEADER_SIZE))?;
+        writer.write_all(&index)?;
+        writer.seek(SeekFrom::Start(start + position))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_u32, Compression, Pack, PackWriter, ENTRY_SIZE, HEADER_SIZE};
+    use crate::{AsyncFileManager, LoadStatus, Source};
+    use futures::executor::ThreadPool;
+    use std::{
+        fs,
+        io::ErrorKind,
+        path::{Path, PathBuf},
+        sync::Arc,
+    };
+
+    fn write_pack(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}_{}", std::process::id(), name));
+        let mut writer = PackWriter::new().with_alignment(64);
+        writer.add_dir("testfiles", Compression::Deflate).unwrap();
+        writer
+            .add("textures/./raw.bin", (0..=255).collect(), Compression::None)
+            .unwrap();
+        writer.add("tiny", vec![7], Compression::Deflate).unwrap();
+        writer.write(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = write_pack("async_filemanager_round_trip.pack");
+        let pack = Pack::open(&path).unwrap();
+        for entry in fs::read_dir("testfiles").unwrap() {
+            let name = PathBuf::from(entry.unwrap().file_name());
+            assert_eq!(
+                pack.read(&name).unwrap(),
+                fs::read(Path::new("testfiles").join(&name)).unwrap()
+            );
+        }
+        assert!(pack.entries().iter().all(|entry| entry.offset % 64 == 0));
+        let entry = pack.entry("base.ron").unwrap();
+        assert_eq!(entry.compression, Compression::Deflate);
+        assert!(entry.stored_size < entry.size);
+        // Incompressible files are stored as they are.
+        assert_eq!(pack.entry("tiny").unwrap().compression, Compression::None);
+        assert_eq!(
+            pack.read_range(Path::new("textures/raw.bin"), 250..300)
+                .unwrap(),
+            vec![250, 251, 252, 253, 254, 255]
+        );
+        assert_eq!(
+            pack.read_dir(Path::new("textures")).unwrap(),
+            vec![PathBuf::from("textures/raw.bin")]
+        );
+        assert!(pack.metadata(Path::new("textures")).unwrap().is_dir);
+        assert_eq!(
+            pack.read(Path::new("missing.ron")).unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn checksum() {
+        let path = write_pack("async_filemanager_checksum.pack");
+        let offset = Pack::open(&path)
+            .unwrap()
+            .entry("textures/raw.bin")
+            .unwrap()
+            .offset;
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[offset as usize] ^= 1;
+        fs::write(&path, bytes).unwrap();
+        let pack = Pack::open(&path).unwrap();
+        assert_eq!(
+            pack.read(Path::new("textures/raw.bin")).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert!(pack.read(Path::new("base.ron")).is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// Header and index fields pointing past the file fail to open, sizes a blob inflates
+    /// beyond fail to read.
+    #[test]
+    fn corrupt() {
+        let path = write_pack("async_filemanager_corrupt.pack");
+        let bytes = fs::read(&path).unwrap();
+        let (position, entry) = Pack::open(&path)
+            .unwrap()
+            .entries()
+            .iter()
+            .position(|entry| entry.name == "base.ron")
+            .map(|index| (HEADER_SIZE + index as u64 * ENTRY_SIZE, index))
+            .unwrap();
+        let corrupted = |offset: u64, value: u64| {
+            let mut bytes = bytes.clone();
+            let offset = offset as usize;
+            bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+            fs::write(&path, bytes).unwrap();
+        };
+        // Length of the name table.
+        corrupted(24, u64::MAX - 8);
+        assert_eq!(
+            Pack::open(&path).err().unwrap().kind(),
+            ErrorKind::InvalidData
+        );
+        // Stored size of the entry.
+        corrupted(position + 16, u64::MAX);
+        assert_eq!(
+            Pack::open(&path).err().unwrap().kind(),
+            ErrorKind::InvalidData
+        );
+        // Size of the entry once inflated.
+        corrupted(position + 24, 1);
+        let pack = Pack::open(&path).unwrap();
+        assert_eq!(
+            pack.read_entry(&pack.entries()[entry]).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[derive(Debug)]
+    struct LoadedFile {
+        bytes: Vec<u8>,
+    }
+
+    impl From<(PathBuf, Vec<u8>)> for LoadedFile {
+        fn from((_path, bytes): (PathBuf, Vec<u8>)) -> Self {
+            LoadedFile { bytes }
+        }
+    }
+
+    #[test]
+    fn manager() {
+        let path = write_pack("async_filemanager_manager.pack");
+        let pool = Arc::new(ThreadPool::new().unwrap());
+        let pack = Arc::new(Pack::open(&path).unwrap());
+        let mut manager = AsyncFileManager::<LoadedFile>::with_source(pool, pack);
+        futures::executor::block_on(async {
+            manager.load("material.ron").await;
+            let file = match manager.get("material.ron").await {
+                LoadStatus::Loaded(file) => file,
+                LoadStatus::Loading(f) => f.await.unwrap(),
+                _ => panic!(),
+            };
+            assert_eq!(file.bytes, fs::read("testfiles/material.ron").unwrap());
+        });
+        fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn index_limits() {
+        assert_eq!(to_u32(u64::from(u32::MAX), "entries").unwrap(), u32::MAX);
+        let error = to_u32(u64::from(u32::MAX) + 1, "entries").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,547 @@
+use memmap::{Mmap, MmapOptions};
+use std::{
+    fmt, fs,
+    io::{Cursor, Error, ErrorKind, Read, Seek, SeekFrom},
+    ops::{Deref, Range},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+/// How often `FileSystem::watch` checks the watched files for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(50);
+
+/// An open file that threads read from at offsets, without a shared cursor where the
+/// platform allows it and under a lock elsewhere.
+#[derive(Debug)]
+pub(crate) struct ConcurrentFile {
+    #[cfg(any(unix, windows))]
+    file: fs::File,
+    #[cfg(not(any(unix, windows)))]
+    file: Mutex<fs::File>,
+}
+
+impl ConcurrentFile {
+    pub(crate) fn new(file: fs::File) -> Self {
+        Self {
+            #[cfg(any(unix, windows))]
+            file,
+            #[cfg(not(any(unix, windows)))]
+            file: Mutex::new(file),
+        }
+    }
+    #[cfg(unix)]
+    pub(crate) fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize, Error> {
+        std::os::unix::fs::FileExt::read_at(&self.file, buf, offset)
+    }
+    #[cfg(windows)]
+    pub(crate) fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize, Error> {
+        std::os::windows::fs::FileExt::seek_read(&self.file, buf, offset)
+    }
+    #[cfg(not(any(unix, windows)))]
+    pub(crate) fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize, Error> {
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(offset))?;
+        file.read(buf)
+    }
+    /// Fills `buf` from `offset`, failing with `UnexpectedEof` if the file ends before.
+    pub(crate) fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> Result<(), Error> {
+        while !buf.is_empty() {
+            match self.read_at(buf, offset) {
+                Ok(0) => return Err(Error::new(ErrorKind::UnexpectedEof, "file ends early")),
+                Ok(read) => {
+                    buf = &mut buf[read..];
+                    offset += read as u64;
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How the bytes of a file are obtained, see `FileBytes`. Files are read by default,
+/// modes mapping them are only made by the unsafe `map` and `map_above`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReadMode {
+    /// Files of at least this many bytes are mapped.
+    map_above: Option<u64>,
+}
+
+impl ReadMode {
+    /// Read into memory owned by the decoder.
+    pub const READ: ReadMode = ReadMode { map_above: None };
+    /// Map the file into memory, sources that can not map read it instead.
+    ///
+    /// # Safety
+    ///
+    /// Mapped files must not be truncated or written to while their `FileBytes` are alive,
+    /// by this process or any other. The bytes would change under the decoder, or accessing
+    /// them would fault. Replace files atomically instead, mappings keep the old contents then.
+    pub unsafe fn map() -> Self {
+        Self::map_above(0)
+    }
+    /// Map files of at least `threshold` bytes, read smaller ones.
+    ///
+    /// # Safety
+    ///
+    /// See `map`.
+    pub unsafe fn map_above(threshold: u64) -> Self {
+        Self {
+            map_above: Some(threshold),
+        }
+    }
+    /// Whether a file of `len` bytes is to be mapped.
+    pub fn maps(self, len: u64) -> bool {
+        matches!(self.map_above, Some(threshold) if len >= threshold)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Bytes {
+    Owned(Arc<Vec<u8>>),
+    /// The part of the mapping the file or range read takes up.
+    Mapped(Arc<Mmap>, Range<usize>),
+}
+
+/// The contents of a file, read into memory or mapped. Clones share the bytes.
+///
+/// Files are only mapped in a `ReadMode` made by the unsafe `ReadMode::map`, whose caller
+/// guarantees the file is left alone while mapped.
+#[derive(Debug, Clone)]
+pub struct FileBytes {
+    bytes: Bytes,
+}
+
+impl FileBytes {
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.bytes, Bytes::Mapped(..))
+    }
+    /// The bytes as an owned `Vec`, copied if they are mapped or shared.
+    pub fn into_vec(self) -> Vec<u8> {
+        match self.bytes {
+            Bytes::Owned(bytes) => Arc::try_unwrap(bytes).unwrap_or_else(|bytes| bytes.to_vec()),
+            Bytes::Mapped(map, range) => map[range].to_vec(),
+        }
+    }
+}
+
+impl From<Vec<u8>> for FileBytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes: Bytes::Owned(Arc::new(bytes)),
+        }
+    }
+}
+
+impl Deref for FileBytes {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        match &self.bytes {
+            Bytes::Owned(bytes) => bytes,
+            Bytes::Mapped(map, range) => &map[range.clone()],
+        }
+    }
+}
+
+impl AsRef<[u8]> for FileBytes {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+/// Size and modification time of a file or directory in a `Source`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub len: u64,
+    /// `None` if the source does not keep modification times.
+    pub modified: Option<SystemTime>,
+    pub is_dir: bool,
+}
+
+/// A file opened to be read piece by piece, see `Source::open`.
+pub struct OpenFile {
+    pub reader: Box<dyn Read + Send>,
+    /// Size of the file if the source knows it before it is read.
+    pub size: Option<u64>,
+    /// See `Source::read_layer`.
+    pub layer: Option<String>,
+}
+
+/// Storage files are read from, e.g. the local filesystem or an archive.
+/// Paths are relative to the source, methods are called on the worker pool.
+pub trait Source: Send + Sync {
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Error>;
+    /// Like `read`, also naming the layer the file was taken from for sources combining
+    /// several, see `Vfs`.
+    fn read_layer(&self, path: &Path) -> Result<(Vec<u8>, Option<String>), Error> {
+        self.read(path).map(|bytes| (bytes, None))
+    }
+    /// Like `read_layer`, sources that can map files do so as `mode` asks.
+    fn read_with(&self, path: &Path, mode: ReadMode) -> Result<(FileBytes, Option<String>), Error> {
+        let _ = mode;
+        self.read_layer(path)
+            .map(|(bytes, layer)| (FileBytes::from(bytes), layer))
+    }
+    /// Opens the file to stream it to its decoder, sources that can not stream read it at once.
+    fn open(&self, path: &Path) -> Result<OpenFile, Error> {
+        let (bytes, layer) = self.read_layer(path)?;
+        Ok(OpenFile {
+            size: Some(bytes.len() as u64),
+            reader: Box::new(Cursor::new(bytes)),
+            layer,
+        })
+    }
+    /// The bytes of `range` in the file, fewer if the file ends before it.
+    fn read_range(&self, path: &Path, range: Range<u64>) -> Result<Vec<u8>, Error> {
+        let bytes = self.read(path)?;
+        let start = (range.start as usize).min(bytes.len());
+        let end = (range.end as usize).max(start).min(bytes.len());
+        Ok(bytes[start..end].to_vec())
+    }
+    /// Like `read_range`, naming the layer as `read_layer` does. Sources that can map files
+    /// do so as `mode` asks, and hand out `range` of the mapping.
+    fn read_range_with(
+        &self,
+        path: &Path,
+        range: Range<u64>,
+        mode: ReadMode,
+    ) -> Result<(FileBytes, Option<String>), Error> {
+        let _ = mode;
+        self.read_range(path, range)
+            .map(|bytes| (FileBytes::from(bytes), None))
+    }
+    fn metadata(&self, path: &Path) -> Result<Metadata, Error>;
+    /// Where the file is on the local filesystem if the source reads it from there,
+    /// loads may read it themselves then, e.g. with io_uring.
+    fn local_path(&self, path: &Path) -> Option<PathBuf> {
+        let _ = path;
+        None
+    }
+    /// Paths of the entries of a directory, sorted.
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error>;
+    /// Calls `on_change` whenever the file changes until the returned `Watch` is dropped.
+    /// Sources that can not watch return an `ErrorKind::Unsupported` error.
+    fn watch(&self, path: &Path, on_change: Box<dyn Fn(&Path) + Send>) -> Result<Watch, Error> {
+        let _ = on_change;
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("{}: watching is not supported", path.display()),
+        ))
+    }
+}
+
+/// Stops watching a file when dropped, see `Source::watch`.
+pub struct Watch {
+    stopped: Arc<AtomicBool>,
+}
+
+impl Watch {
+    /// A watch that sets `stopped` once dropped, for sources polling in the background.
+    pub fn new(stopped: Arc<AtomicBool>) -> Self {
+        Self { stopped }
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A callback of `Source::watch`, shared so it can be called without the watch list locked.
+pub(crate) type OnChange = Arc<Mutex<Box<dyn Fn(&Path) + Send>>>;
+
+/// A file watched by `FileSystem::watch`.
+struct Watched {
+    full_path: PathBuf,
+    path: PathBuf,
+    version: Option<(SystemTime, u64)>,
+    /// Called without the `Watcher` locked, so it may watch other files.
+    on_change: OnChange,
+    stopped: Arc<AtomicBool>,
+}
+
+/// The files watched through a `FileSystem` and its clones, polled by one background thread
+/// that runs while any are watched.
+#[derive(Default)]
+struct Watcher {
+    files: Vec<Watched>,
+    running: bool,
+}
+
+impl fmt::Debug for Watcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watcher")
+            .field("files", &self.files.len())
+            .field("running", &self.running)
+            .finish()
+    }
+}
+
+impl Watcher {
+    /// Drops stopped watches and returns the callbacks of the files that changed,
+    /// `None` once nothing is watched and the thread should end.
+    fn poll(&mut self) -> Option<Vec<(PathBuf, OnChange)>> {
+        self.files
+            .retain(|watched| !watched.stopped.load(Ordering::Relaxed));
+        if self.files.is_empty() {
+            self.running = false;
+            return None;
+        }
+        let mut changed = Vec::new();
+        for watched in self.files.iter_mut() {
+            let version = FileSystem::version(&watched.full_path);
+            if version != watched.version {
+                watched.version = version;
+                changed.push((watched.path.clone(), watched.on_change.clone()));
+            }
+        }
+        Some(changed)
+    }
+    fn run(watcher: Arc<Mutex<Watcher>>) {
+        loop {
+            std::thread::sleep(WATCH_INTERVAL);
+            let changed = match watcher.lock().unwrap().poll() {
+                Some(changed) => changed,
+                None => return,
+            };
+            for (path, on_change) in changed {
+                (on_change.lock().unwrap())(&path);
+            }
+        }
+    }
+}
+
+/// Files in a directory of the local filesystem, the working directory by default.
+#[derive(Debug, Clone, Default)]
+pub struct FileSystem {
+    root: PathBuf,
+    watcher: Arc<Mutex<Watcher>>,
+}
+
+impl FileSystem {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_owned(),
+            watcher: Arc::default(),
+        }
+    }
+    fn version(path: &Path) -> Option<(SystemTime, u64)> {
+        let metadata = fs::metadata(path).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    }
+    /// `range` of the mapped file, fewer bytes if it ends before, if `mode` asks to map it.
+    /// `None` if it is to be read or can not be mapped, e.g. as it is empty.
+    fn map(file: &fs::File, range: Range<u64>, mode: ReadMode) -> Result<Option<FileBytes>, Error> {
+        let len = file.metadata()?.len();
+        if mode == ReadMode::READ || !mode.maps(len) {
+            return Ok(None);
+        }
+        // Safe as the maker of `mode` guarantees the file is not modified while mapped.
+        let map = match unsafe { MmapOptions::new().map(file) } {
+            Ok(map) => map,
+            Err(_) => return Ok(None),
+        };
+        let start = range.start.min(len) as usize;
+        let end = range.end.max(range.start).min(len) as usize;
+        Ok(Some(FileBytes {
+            bytes: Bytes::Mapped(Arc::new(map), start..end),
+        }))
+    }
+}
+
+impl Source for FileSystem {
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        fs::read(self.root.join(path))
+    }
+    fn open(&self, path: &Path) -> Result<OpenFile, Error> {
+        let file = fs::File::open(self.root.join(path))?;
+        Ok(OpenFile {
+            size: Some(file.metadata()?.len()),
+            reader: Box::new(file),
+            layer: None,
+        })
+    }
+    fn read_range(&self, path: &Path, range: Range<u64>) -> Result<Vec<u8>, Error> {
+        let mut file = fs::File::open(self.root.join(path))?;
+        file.seek(SeekFrom::Start(range.start))?;
+        let mut bytes = Vec::new();
+        file.take(range.end.saturating_sub(range.start))
+            .read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+    /// Maps the file if `mode` asks to, reads it if mapping fails, e.g. for empty files.
+    fn read_with(&self, path: &Path, mode: ReadMode) -> Result<(FileBytes, Option<String>), Error> {
+        let file = fs::File::open(self.root.join(path))?;
+        if let Some(bytes) = FileSystem::map(&file, 0..u64::MAX, mode)? {
+            return Ok((bytes, None));
+        }
+        let mut bytes = Vec::new();
+        (&file).read_to_end(&mut bytes)?;
+        Ok((bytes.into(), None))
+    }
+    /// Maps the file if `mode` asks to, reads only `range` if mapping fails.
+    fn read_range_with(
+        &self,
+        path: &Path,
+        range: Range<u64>,
+        mode: ReadMode,
+    ) -> Result<(FileBytes, Option<String>), Error> {
+        if mode != ReadMode::READ {
+            let file = fs::File::open(self.root.join(path))?;
+            if let Some(bytes) = FileSystem::map(&file, range.clone(), mode)? {
+                return Ok((bytes, None));
+            }
+        }
+        self.read_range(path, range)
+            .map(|bytes| (bytes.into(), None))
+    }
+    fn metadata(&self, path: &Path) -> Result<Metadata, Error> {
+        let metadata = fs::metadata(self.root.join(path))?;
+        Ok(Metadata {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            is_dir: metadata.is_dir(),
+        })
+    }
+    fn local_path(&self, path: &Path) -> Option<PathBuf> {
+        Some(self.root.join(path))
+    }
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+        let mut entries = fs::read_dir(self.root.join(path))?
+            .map(|entry| entry.map(|entry| path.join(entry.file_name())))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+        Ok(entries)
+    }
+    /// Polls the modification time and size of the file on a background thread,
+    /// shared by every file watched through this `FileSystem` and its clones.
+    fn watch(&self, path: &Path, on_change: Box<dyn Fn(&Path) + Send>) -> Result<Watch, Error> {
+        let full_path = self.root.join(path);
+        let stopped = Arc::new(AtomicBool::new(false));
+        let mut watcher = self.watcher.lock().unwrap();
+        if !watcher.running {
+            let shared = self.watcher.clone();
+            std::thread::Builder::new()
+                .name(format!("watch {}", self.root.display()))
+                .spawn(move || Watcher::run(shared))?;
+            watcher.running = true;
+        }
+        watcher.files.push(Watched {
+            version: FileSystem::version(&full_path),
+            full_path,
+            path: path.to_owned(),
+            on_change: Arc::new(Mutex::new(on_change)),
+            stopped: stopped.clone(),
+        });
+        Ok(Watch::new(stopped))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileSystem, ReadMode, Source};
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        sync::mpsc::channel,
+        time::Duration,
+    };
+
+    #[test]
+    fn file_system() {
+        let source = FileSystem::new("testfiles");
+        let path = Path::new("material.ron");
+        let bytes = source.read(path).unwrap();
+        assert_eq!(source.read_range(path, 6..10).unwrap(), &bytes[6..10]);
+        assert_eq!(source.read_range(path, 6..1000).unwrap(), &bytes[6..]);
+        let metadata = source.metadata(path).unwrap();
+        assert_eq!(metadata.len, bytes.len() as u64);
+        assert!(!metadata.is_dir);
+        assert!(source.metadata(Path::new("")).unwrap().is_dir);
+        assert!(source
+            .read_dir(Path::new(""))
+            .unwrap()
+            .contains(&PathBuf::from("material.ron")));
+        assert!(source.read(Path::new("missing.ron")).is_err());
+    }
+
+    #[test]
+    fn mapping() {
+        let source = FileSystem::new("testfiles");
+        let path = Path::new("material.ron");
+        let (mapped, _) = source.read_with(path, unsafe { ReadMode::map() }).unwrap();
+        assert!(mapped.is_mapped());
+        assert_eq!(&*mapped, &source.read(path).unwrap()[..]);
+        assert!(!source
+            .read_with(path, ReadMode::READ)
+            .unwrap()
+            .0
+            .is_mapped());
+        let len = mapped.len() as u64;
+        assert!(source
+            .read_with(path, unsafe { ReadMode::map_above(len) })
+            .unwrap()
+            .0
+            .is_mapped());
+        assert!(!source
+            .read_with(path, unsafe { ReadMode::map_above(len + 1) })
+            .unwrap()
+            .0
+            .is_mapped());
+        assert_eq!(mapped.into_vec(), source.read(path).unwrap());
+
+        // Empty files can not be mapped, they are read instead.
+        let dir =
+            std::env::temp_dir().join(format!("async_filemanager_mapping_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("empty"), "").unwrap();
+        let (empty, _) = FileSystem::new(&dir)
+            .read_with(Path::new("empty"), unsafe { ReadMode::map() })
+            .unwrap();
+        assert!(empty.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn watch() {
+        let dir =
+            std::env::temp_dir().join(format!("async_filemanager_watch_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("watched"), "old").unwrap();
+        fs::write(dir.join("other"), "old").unwrap();
+        let source = FileSystem::new(&dir);
+        let (tx, rx) = channel();
+        let watches = ["watched", "other"]
+            .iter()
+            .map(|path| {
+                let tx = tx.clone();
+                source
+                    .watch(
+                        Path::new(path),
+                        Box::new(move |path| {
+                            let _ = tx.send(path.to_owned());
+                        }),
+                    )
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        fs::write(dir.join("watched"), "changed").unwrap();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            PathBuf::from("watched")
+        );
+        fs::write(dir.join("other"), "changed").unwrap();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            PathBuf::from("other")
+        );
+        drop(watches);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
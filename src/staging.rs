@@ -0,0 +1,151 @@
+/// Required alignment of `bytes_per_row` (and our region offsets) for buffer to texture copies.
+pub const COPY_ROW_ALIGNMENT: u32 = 256;
+
+/// `value / divisor` rounded up. `u64::div_ceil` needs Rust 1.73, newer than `rust-version`.
+pub(crate) fn div_ceil(value: u64, divisor: u64) -> u64 {
+    (value + divisor - 1) / divisor
+}
+
+fn align(value: u64, alignment: u64) -> u64 {
+    div_ceil(value, alignment) * alignment
+}
+
+/// Location of one upload inside the staging buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StagingRegion {
+    pub offset: u64,
+    /// Row pitch in the staging buffer, a multiple of `COPY_ROW_ALIGNMENT`.
+    pub bytes_per_row: u32,
+    /// Unpadded row length of the source data.
+    pub row_size: u32,
+    pub rows: u32,
+}
+
+/// Packs several row-based uploads into a single staging buffer.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StagingPlan {
+    regions: Vec<StagingRegion>,
+    size: u64,
+}
+
+impl StagingPlan {
+    /// Takes `(row_size, rows)` of every upload in order.
+    pub fn new<I: IntoIterator<Item = (u32, u32)>>(layouts: I) -> Self {
+        let mut plan = Self::default();
+        for (row_size, rows) in layouts {
+            let bytes_per_row = align(row_size as u64, COPY_ROW_ALIGNMENT as u64) as u32;
+            plan.regions.push(StagingRegion {
+                offset: plan.size,
+                bytes_per_row,
+                row_size,
+                rows,
+            });
+            plan.size += bytes_per_row as u64 * rows as u64;
+        }
+        plan
+    }
+    pub fn regions(&self) -> &[StagingRegion] {
+        &self.regions
+    }
+    /// Size of the staging buffer in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+    /// Lays out `sources` (one per region, tightly packed rows) with padded rows.
+    pub fn write(&self, sources: &[&[u8]]) -> Vec<u8> {
+        assert_eq!(
+            sources.len(),
+            self.regions.len(),
+            "Staging plan has {} regions but got {} sources!",
+            self.regions.len(),
+            sources.len()
+        );
+        let mut staging = vec![0; self.size as usize];
+        for (region, source) in self.regions.iter().zip(sources) {
+            let row_size = region.row_size as usize;
+            for (row, data) in source
+                .chunks(row_size)
+                .take(region.rows as usize)
+                .enumerate()
+            {
+                let start = region.offset as usize + row * region.bytes_per_row as usize;
+                staging[start..start + data.len()].copy_from_slice(data);
+            }
+        }
+        staging
+    }
+    pub fn stats(&self) -> BatchStats {
+        let data_bytes = self
+            .regions
+            .iter()
+            .map(|region| region.row_size as u64 * region.rows as u64)
+            .sum();
+        BatchStats {
+            uploads: self.regions.len(),
+            data_bytes,
+            staging_bytes: self.size,
+        }
+    }
+}
+
+/// Statistics of one batched upload submission.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BatchStats {
+    pub uploads: usize,
+    /// Bytes of texel data without row padding.
+    pub data_bytes: u64,
+    pub staging_bytes: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BatchStats, StagingPlan, StagingRegion};
+
+    #[test]
+    fn packing() {
+        let plan = StagingPlan::new(vec![(8, 2), (256, 1), (300, 2)]);
+        assert_eq!(
+            plan.regions(),
+            &[
+                StagingRegion {
+                    offset: 0,
+                    bytes_per_row: 256,
+                    row_size: 8,
+                    rows: 2
+                },
+                StagingRegion {
+                    offset: 512,
+                    bytes_per_row: 256,
+                    row_size: 256,
+                    rows: 1
+                },
+                StagingRegion {
+                    offset: 768,
+                    bytes_per_row: 512,
+                    row_size: 300,
+                    rows: 2
+                },
+            ]
+        );
+        assert_eq!(plan.size(), 1792);
+        assert_eq!(
+            plan.stats(),
+            BatchStats {
+                uploads: 3,
+                data_bytes: 16 + 256 + 600,
+                staging_bytes: 1792
+            }
+        );
+    }
+
+    #[test]
+    fn writing() {
+        let plan = StagingPlan::new(vec![(4, 2), (4, 1)]);
+        let staging = plan.write(&[&[1, 2, 3, 4, 5, 6, 7, 8], &[9, 10, 11, 12]]);
+        assert_eq!(staging.len(), 768);
+        assert_eq!(&staging[0..4], &[1, 2, 3, 4]);
+        assert!(staging[4..256].iter().all(|b| *b == 0));
+        assert_eq!(&staging[256..260], &[5, 6, 7, 8]);
+        assert_eq!(&staging[512..516], &[9, 10, 11, 12]);
+    }
+}
@@ -0,0 +1,345 @@
+use crate::FileBytes;
+use std::{
+    convert::TryFrom,
+    io::{Error, ErrorKind, Read},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// Bytes handed out by `ChunkReader::next_chunk` unless the load asks for other chunks,
+/// see `FileLoadFuture::with_chunk_size`.
+pub(crate) const CHUNK_SIZE: usize = 1 << 20;
+
+/// Size of the file in `LoadProgress` while it is unknown.
+const UNKNOWN: u64 = u64::MAX;
+
+/// Decodes a file while it is read, so large files need not be held in memory at once.
+///
+/// Every `TryFrom<(PathBuf, Vec<u8>)>` type decodes this way after reading the whole file,
+/// its errors are reported as `ErrorKind::InvalidData` without a message. Types implement
+/// `StreamDecode` themselves instead to report why decoding failed.
+pub trait StreamDecode: Sized {
+    fn decode(path: PathBuf, reader: &mut ChunkReader) -> Result<Self, Error>;
+}
+
+impl<T> StreamDecode for T
+where
+    T: TryFrom<(PathBuf, Vec<u8>)>,
+{
+    fn decode(path: PathBuf, reader: &mut ChunkReader) -> Result<Self, Error> {
+        let bytes = reader.read_to_vec()?;
+        T::try_from((path, bytes)).map_err(|_| Error::new(ErrorKind::InvalidData, ""))
+    }
+}
+
+/// Decodes only the start of a file, e.g. the size of an image, to decide whether to load
+/// all of it. Load a `Header<T>` to run it.
+pub trait DecodeHeader {
+    type Header: Send + Sync + 'static;
+    /// Reads only as far as the header goes.
+    fn decode_header(path: PathBuf, reader: &mut ChunkReader) -> Result<Self::Header, Error>;
+}
+
+/// The header of a `T`, loaded like any file, e.g. by an `AsyncFileManager<Header<T>>`.
+pub struct Header<T: DecodeHeader>(pub T::Header);
+
+impl<T: DecodeHeader> StreamDecode for Header<T> {
+    fn decode(path: PathBuf, reader: &mut ChunkReader) -> Result<Self, Error> {
+        T::decode_header(path, reader).map(Header)
+    }
+}
+
+/// How much of a file its decoder has read, updated by the worker as it goes.
+#[derive(Debug)]
+pub struct LoadProgress {
+    read: AtomicU64,
+    size: AtomicU64,
+}
+
+impl LoadProgress {
+    pub(crate) fn new() -> Self {
+        Self {
+            read: AtomicU64::new(0),
+            size: AtomicU64::new(UNKNOWN),
+        }
+    }
+    /// Bytes read so far.
+    pub fn read(&self) -> u64 {
+        self.read.load(Ordering::Relaxed)
+    }
+    /// Size of the file, `None` before it is opened or while the source does not know it.
+    pub fn size(&self) -> Option<u64> {
+        match self.size.load(Ordering::Relaxed) {
+            UNKNOWN => None,
+            size => Some(size),
+        }
+    }
+    /// Share of the file read from 0 to 1, `None` while its size is unknown.
+    pub fn fraction(&self) -> Option<f64> {
+        let size = self.size()?;
+        if size == 0 {
+            return Some(1.0);
+        }
+        Some((self.read() as f64 / size as f64).min(1.0))
+    }
+    pub(crate) fn start(&self, size: Option<u64>) {
+        self.read.store(0, Ordering::Relaxed);
+        self.size.store(size.unwrap_or(UNKNOWN), Ordering::Relaxed);
+    }
+    pub(crate) fn advance(&self, read: usize) {
+        self.read.fetch_add(read as u64, Ordering::Relaxed);
+    }
+    /// The whole file is read, its size is known now.
+    pub(crate) fn finish(&self) {
+        self.size.store(self.read(), Ordering::Relaxed);
+    }
+}
+
+enum Input {
+    Bytes { bytes: FileBytes, position: usize },
+    Stream(Box<dyn Read + Send>),
+}
+
+/// A file being read, handed to `StreamDecode::decode`.
+///
+/// `next_chunk` hands out the file in chunks of `chunk_size` bytes, decoders built on readers
+/// use it as `Read` instead. Everything read is counted in the `LoadProgress` of the load.
+pub struct ChunkReader {
+    input: Input,
+    chunk: Vec<u8>,
+    chunk_size: usize,
+    size: Option<u64>,
+    progress: Arc<LoadProgress>,
+}
+
+impl ChunkReader {
+    /// Reads from `reader`, a file of `size` bytes if known.
+    pub(crate) fn new(
+        reader: Box<dyn Read + Send>,
+        size: Option<u64>,
+        chunk_size: usize,
+        progress: Arc<LoadProgress>,
+    ) -> Self {
+        progress.start(size);
+        Self {
+            input: Input::Stream(reader),
+            chunk: Vec::new(),
+            chunk_size: chunk_size.max(1),
+            size,
+            progress,
+        }
+    }
+    /// Hands out bytes already in memory, chunks borrow them without copying.
+    pub(crate) fn from_bytes(
+        bytes: FileBytes,
+        chunk_size: usize,
+        progress: Arc<LoadProgress>,
+    ) -> Self {
+        let size = Some(bytes.len() as u64);
+        progress.start(size);
+        Self {
+            input: Input::Bytes { bytes, position: 0 },
+            chunk: Vec::new(),
+            chunk_size: chunk_size.max(1),
+            size,
+            progress,
+        }
+    }
+    /// Size of the file, `None` if the source does not know it before it is read.
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+    /// The next `chunk_size` bytes, fewer only for the last chunk. `None` once the file is read.
+    pub fn next_chunk(&mut self) -> Result<Option<&[u8]>, Error> {
+        let chunk = match &mut self.input {
+            Input::Bytes { bytes, position } => {
+                let start = *position;
+                *position = (start + self.chunk_size).min(bytes.len());
+                &bytes[start..*position]
+            }
+            Input::Stream(reader) => {
+                self.chunk.resize(self.chunk_size, 0);
+                let mut filled = 0;
+                while filled < self.chunk.len() {
+                    match reader.read(&mut self.chunk[filled..]) {
+                        Ok(0) => break,
+                        Ok(read) => filled += read,
+                        Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+                self.chunk.truncate(filled);
+                &self.chunk[..]
+            }
+        };
+        if chunk.is_empty() {
+            self.progress.finish();
+            Ok(None)
+        } else {
+            self.progress.advance(chunk.len());
+            Ok(Some(chunk))
+        }
+    }
+    /// The rest of the file at once. Bytes read into memory before are not copied
+    /// if nothing was taken from them yet.
+    pub fn read_to_vec(&mut self) -> Result<Vec<u8>, Error> {
+        self.read_rest().map(FileBytes::into_vec)
+    }
+    /// Like `read_to_vec`, keeping mapped bytes mapped.
+    pub(crate) fn read_rest(&mut self) -> Result<FileBytes, Error> {
+        let rest = match &mut self.input {
+            Input::Bytes { bytes, position } if *position == 0 => {
+                std::mem::replace(bytes, FileBytes::from(Vec::new()))
+            }
+            Input::Bytes { bytes, position } => {
+                let rest = bytes[*position..].to_vec();
+                *position = bytes.len();
+                rest.into()
+            }
+            Input::Stream(reader) => {
+                let remaining = self.size.unwrap_or(0).saturating_sub(self.progress.read());
+                let mut rest = Vec::with_capacity(remaining as usize);
+                reader.read_to_end(&mut rest)?;
+                rest.into()
+            }
+        };
+        self.progress.advance(rest.len());
+        self.progress.finish();
+        Ok(rest)
+    }
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let read = match &mut self.input {
+            Input::Bytes { bytes, position } => {
+                let read = (&bytes[*position..]).read(buf)?;
+                *position += read;
+                read
+            }
+            Input::Stream(reader) => reader.read(buf)?,
+        };
+        if read == 0 && !buf.is_empty() {
+            self.progress.finish();
+        } else {
+            self.progress.advance(read);
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChunkReader, DecodeHeader, Header, StreamDecode};
+    use crate::{AsyncFileManager, FileLoadFuture, LoadStatus, ReadMode};
+    use futures::executor::ThreadPoolBuilder;
+    use std::{
+        convert::TryFrom,
+        io::{BufRead, BufReader, Error, ErrorKind},
+        path::PathBuf,
+        sync::Arc,
+    };
+
+    /// Counts lines chunk by chunk, remembering the size of every chunk.
+    #[derive(Debug)]
+    struct Lines {
+        count: usize,
+        chunks: Vec<usize>,
+    }
+
+    impl StreamDecode for Lines {
+        fn decode(_path: PathBuf, reader: &mut ChunkReader) -> Result<Self, Error> {
+            let mut lines = Lines {
+                count: 0,
+                chunks: Vec::new(),
+            };
+            while let Some(chunk) = reader.next_chunk()? {
+                lines.count += chunk.iter().filter(|byte| **byte == b'\n').count();
+                lines.chunks.push(chunk.len());
+            }
+            Ok(lines)
+        }
+    }
+
+    /// The first line is the header.
+    impl DecodeHeader for Lines {
+        type Header = String;
+        fn decode_header(_path: PathBuf, reader: &mut ChunkReader) -> Result<String, Error> {
+            let mut line = String::new();
+            BufReader::with_capacity(8, reader).read_line(&mut line)?;
+            Ok(line)
+        }
+    }
+
+    #[test]
+    fn chunks() {
+        let pool = Arc::new(ThreadPoolBuilder::new().create().unwrap());
+        let path = PathBuf::new().join("benches/benchfiles/s01");
+        // The file is 182 bytes long, 11 chunks of 16 and one of 6.
+        let mut expected = vec![16; 11];
+        expected.push(6);
+        futures::executor::block_on(async {
+            for mode in &[ReadMode::READ, unsafe { ReadMode::map() }] {
+                let f = FileLoadFuture::<Lines>::new(&path, pool.clone())
+                    .with_chunk_size(16)
+                    .with_read_mode(*mode);
+                let progress = f.progress();
+                let lines = f.await.unwrap();
+                assert_eq!(lines.chunks, expected);
+                assert_eq!(lines.count, 13);
+                assert_eq!((progress.read(), progress.size()), (182, Some(182)));
+                assert_eq!(progress.fraction(), Some(1.0));
+            }
+
+            // Only as much as the header takes is read.
+            let f = FileLoadFuture::<Header<Lines>>::new(&path, pool.clone());
+            let progress = f.progress();
+            assert_eq!(f.await.unwrap().0, "\r\n");
+            assert_eq!(progress.read(), 8);
+        });
+
+        let mut manager = AsyncFileManager::<Lines>::new(pool);
+        manager.set_chunk_size(100);
+        futures::executor::block_on(async {
+            manager.load(&path).await;
+            let lines = match manager.get(&path).await {
+                LoadStatus::Loaded(lines) => lines,
+                LoadStatus::Loading(f) => f.await.unwrap(),
+                _ => panic!(),
+            };
+            assert_eq!(lines.chunks, vec![100, 82]);
+            assert_eq!(manager.progress(&path).unwrap().read(), 182);
+            assert_eq!(manager.stats(&path).unwrap().size, 182);
+        });
+    }
+    /// Only empty files, with an error that can not be displayed.
+    #[derive(Debug)]
+    struct Empty;
+
+    impl TryFrom<(PathBuf, Vec<u8>)> for Empty {
+        type Error = ();
+        fn try_from((_path, bytes): (PathBuf, Vec<u8>)) -> Result<Self, ()> {
+            if bytes.is_empty() {
+                Ok(Empty)
+            } else {
+                Err(())
+            }
+        }
+    }
+
+    #[test]
+    fn try_from() {
+        let pool = Arc::new(ThreadPoolBuilder::new().create().unwrap());
+        let path = PathBuf::new().join("benches/benchfiles/s01");
+        futures::executor::block_on(async {
+            let error = FileLoadFuture::<Empty>::new(&path, pool).await.unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        });
+    }
+}
@@ -0,0 +1,188 @@
+use crate::{
+    fileloader::FileLoadFuture,
+    gpuasset::GpuAsset,
+    gpuloader::{GpuLoadFuture, UploadDuration},
+    imagedata::ImageData,
+    FileSystem, Source,
+};
+use futures::executor::ThreadPool;
+use futures::Future;
+use std::path::Path;
+use std::{
+    io::Error,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::Poll,
+};
+use wgpu::{Device, Queue, Texture};
+
+/// A texture uploaded from a file, optionally keeping the decoded `ImageData`.
+#[derive(Debug)]
+pub struct LoadedTexture {
+    texture: Arc<Texture>,
+    image_data: Option<Arc<ImageData>>,
+    size: u64,
+}
+
+impl LoadedTexture {
+    pub(crate) fn new(
+        texture: Arc<Texture>,
+        image_data: Option<Arc<ImageData>>,
+        size: u64,
+    ) -> Self {
+        Self {
+            texture,
+            image_data,
+            size,
+        }
+    }
+    pub fn texture(&self) -> &Arc<Texture> {
+        &self.texture
+    }
+    /// `None` if the manager was told to drop the `ImageData` after upload.
+    pub fn image_data(&self) -> Option<&Arc<ImageData>> {
+        self.image_data.as_ref()
+    }
+    /// Estimated GPU memory of the texture in bytes, see `GpuAsset::gpu_size`.
+    pub fn gpu_size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// Called with the result of a texture load when it completes.
+pub(crate) type OnComplete = Box<dyn FnOnce(&Result<Arc<LoadedTexture>, Arc<Error>>) + Send>;
+
+/// Reads, decodes and uploads a texture in one future.
+pub struct TextureLoadFuture {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    pool: Arc<ThreadPool>,
+    keep_image_data: bool,
+    duration: UploadDuration,
+    status: LoadStatus,
+    on_complete: Option<OnComplete>,
+}
+
+impl TextureLoadFuture {
+    /// Reads the file from the local filesystem.
+    pub fn new<P: AsRef<Path>>(
+        path: P,
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        pool: Arc<ThreadPool>,
+        keep_image_data: bool,
+    ) -> Self {
+        let source = Arc::new(FileSystem::default());
+        Self::with_source(path, source, device, queue, pool, keep_image_data)
+    }
+    pub fn with_source<P: AsRef<Path>>(
+        path: P,
+        source: Arc<dyn Source>,
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        pool: Arc<ThreadPool>,
+        keep_image_data: bool,
+    ) -> Self {
+        Self {
+            status: LoadStatus::Reading(FileLoadFuture::with_source(path, pool.clone(), source)),
+            device,
+            queue,
+            pool,
+            keep_image_data,
+            duration: Arc::new(Mutex::new(None)),
+            on_complete: None,
+        }
+    }
+    /// Resolves to a texture that is uploaded already.
+    pub(crate) fn ready(
+        texture: Arc<LoadedTexture>,
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        pool: Arc<ThreadPool>,
+    ) -> Self {
+        Self {
+            status: LoadStatus::Ready(Some(texture)),
+            device,
+            queue,
+            pool,
+            keep_image_data: true,
+            duration: Arc::new(Mutex::new(None)),
+            on_complete: None,
+        }
+    }
+    /// Calls `on_complete` once the load completes, before the result is returned, so the
+    /// manager gets it even if only the caller awaits the future.
+    pub(crate) fn on_complete(mut self, on_complete: OnComplete) -> Self {
+        self.on_complete = Some(on_complete);
+        self
+    }
+    fn complete(
+        &mut self,
+        result: Result<Arc<LoadedTexture>, Arc<Error>>,
+    ) -> Poll<Result<Arc<LoadedTexture>, Arc<Error>>> {
+        self.status = LoadStatus::Done;
+        if let Some(on_complete) = self.on_complete.take() {
+            on_complete(&result);
+        }
+        Poll::Ready(result)
+    }
+    /// Filled in once the texture is uploaded, see `GpuLoadFuture::upload_duration`.
+    pub fn upload_duration(&self) -> UploadDuration {
+        self.duration.clone()
+    }
+}
+
+enum LoadStatus {
+    Reading(FileLoadFuture<ImageData>),
+    Uploading(Option<Arc<ImageData>>, u64, GpuLoadFuture<ImageData>),
+    Ready(Option<Arc<LoadedTexture>>),
+    Done,
+}
+
+impl Future for TextureLoadFuture {
+    type Output = Result<Arc<LoadedTexture>, Arc<Error>>;
+    fn poll(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            match &mut this.status {
+                LoadStatus::Reading(f) => match Pin::new(f).poll(cx) {
+                    Poll::Ready(Ok(img)) => {
+                        let size = img.gpu_size();
+                        let upload = GpuLoadFuture::new(
+                            img.clone(),
+                            this.device.clone(),
+                            this.queue.clone(),
+                            this.pool.clone(),
+                        );
+                        let img = if this.keep_image_data {
+                            Some(img)
+                        } else {
+                            None
+                        };
+                        this.status = LoadStatus::Uploading(img, size, upload);
+                    }
+                    Poll::Ready(Err(e)) => return this.complete(Err(e)),
+                    Poll::Pending => return Poll::Pending,
+                },
+                LoadStatus::Uploading(img, size, f) => match Pin::new(&mut *f).poll(cx) {
+                    Poll::Ready(result) => {
+                        *this.duration.lock().unwrap() = *f.upload_duration().lock().unwrap();
+                        let result = result.map(|texture| {
+                            Arc::new(LoadedTexture::new(texture, img.take(), *size))
+                        });
+                        return this.complete(result);
+                    }
+                    Poll::Pending => return Poll::Pending,
+                },
+                LoadStatus::Ready(texture) => {
+                    let texture = texture.take().unwrap();
+                    return this.complete(Ok(texture));
+                }
+                LoadStatus::Done => panic!("TextureLoadFuture polled after completion!"),
+            }
+        }
+    }
+}
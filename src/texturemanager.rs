@@ -0,0 +1,289 @@
+use crate::{
+    gpumanager::AsyncGpuManager,
+    imagedata::ImageData,
+    textureloader::{LoadedTexture, TextureLoadFuture},
+    FileSystem, Identifier, LoadStatus, Source,
+};
+use futures::executor::ThreadPool;
+use futures::{future::Shared, FutureExt};
+use std::{
+    collections::HashMap,
+    io::Error,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use wgpu::{Device, Queue};
+
+/// A texture load that completed, waiting to be cached by the manager.
+struct Completed {
+    id: Identifier,
+    /// Number of the load, completions of loads that were replaced since are dropped.
+    load: u64,
+    duration: Duration,
+    result: Result<Arc<LoadedTexture>, Arc<Error>>,
+}
+
+/// Reads, decodes and uploads textures keyed by path.
+///
+/// Uploaded textures are cached in an `AsyncGpuManager`, so they count against its VRAM budget
+/// and are evicted like any other resource of it. Loading a texture that is cached resolves
+/// immediately. Textures are cached once their load completes, also if only the caller of
+/// `load_texture` awaits it.
+#[allow(unused)]
+pub struct AsyncTextureManager {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    pool: Arc<ThreadPool>,
+    source: Arc<dyn Source>,
+    keep_image_data: bool,
+    loading: HashMap<Identifier, (Shared<TextureLoadFuture>, u64)>,
+    completed: Arc<Mutex<Vec<Completed>>>,
+    failed: HashMap<Identifier, Arc<Error>>,
+    loads: u64,
+    textures: AsyncGpuManager<ImageData>,
+    images: HashMap<Identifier, Arc<ImageData>>,
+}
+
+impl AsyncTextureManager {
+    /// Reads textures from the local filesystem.
+    #[allow(unused)]
+    pub fn new(pool: Arc<ThreadPool>, device: Arc<Device>, queue: Arc<Queue>) -> Self {
+        Self::with_source(pool, device, queue, Arc::new(FileSystem::default()))
+    }
+    #[allow(unused)]
+    pub fn with_source(
+        pool: Arc<ThreadPool>,
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        source: Arc<dyn Source>,
+    ) -> Self {
+        Self {
+            textures: AsyncGpuManager::new(pool.clone(), device.clone(), queue.clone()),
+            device,
+            queue,
+            pool,
+            source,
+            keep_image_data: true,
+            loading: HashMap::new(),
+            completed: Arc::new(Mutex::new(Vec::new())),
+            failed: HashMap::new(),
+            loads: 0,
+            images: HashMap::new(),
+        }
+    }
+    /// If `false`, the decoded `ImageData` is dropped once the texture is uploaded.
+    /// Only affects loads started afterwards.
+    #[allow(unused)]
+    pub fn set_keep_image_data(&mut self, keep: bool) {
+        self.keep_image_data = keep;
+    }
+    /// Sets the VRAM budget in bytes, `None` disables eviction. See `AsyncGpuManager::set_budget`.
+    #[allow(unused)]
+    pub fn set_budget(&mut self, budget: Option<u64>) {
+        self.cache_completed();
+        self.textures.set_budget(budget);
+        self.forget_evicted();
+    }
+    #[allow(unused)]
+    pub fn budget(&self) -> Option<u64> {
+        self.textures.budget()
+    }
+    /// Estimated GPU memory of all cached textures in bytes, including those whose load
+    /// completed since the manager was last called.
+    #[allow(unused)]
+    pub fn usage(&self) -> u64 {
+        let completed = self.completed.lock().unwrap();
+        let pending = completed
+            .iter()
+            .filter(|completed| self.is_current(completed))
+            .filter_map(|completed| completed.result.as_ref().ok())
+            .map(|texture| texture.gpu_size())
+            .sum::<u64>();
+        self.textures.usage() + pending
+    }
+    #[allow(unused)]
+    pub async fn load_texture<P: AsRef<Path>>(&mut self, path: P) -> Shared<TextureLoadFuture> {
+        self.cache_completed();
+        let id = Identifier::from(path.as_ref().to_owned());
+        if let Some((f, _)) = self.loading.get(&id) {
+            return f.clone();
+        }
+        if let Some(texture) = self.cached(&id).await {
+            return TextureLoadFuture::ready(
+                texture,
+                self.device.clone(),
+                self.queue.clone(),
+                self.pool.clone(),
+            )
+            .shared();
+        }
+        self.failed.remove(&id);
+        self.loads += 1;
+        let load = self.loads;
+        let f = TextureLoadFuture::with_source(
+            path,
+            self.source.clone(),
+            self.device.clone(),
+            self.queue.clone(),
+            self.pool.clone(),
+            self.keep_image_data,
+        );
+        let duration = f.upload_duration();
+        let completed = self.completed.clone();
+        let completed_id = id.clone();
+        let f = f.on_complete(Box::new(move |result| {
+            completed.lock().unwrap().push(Completed {
+                id: completed_id,
+                load,
+                duration: duration.lock().unwrap().unwrap_or_default(),
+                result: result.clone(),
+            });
+        }));
+        let mut f = f.shared();
+        futures::poll!(&mut f);
+        self.loading.insert(id, (f.clone(), load));
+        self.cache_completed();
+        f
+    }
+    #[allow(unused)]
+    pub async fn get<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> LoadStatus<LoadedTexture, TextureLoadFuture> {
+        let id = Identifier::from(path.as_ref().to_owned());
+        if let Some((f, _)) = self.loading.get(&id) {
+            let mut f = f.clone();
+            if futures::poll!(&mut f).is_pending() {
+                return LoadStatus::Loading(f);
+            }
+        }
+        self.cache_completed();
+        if let Some(e) = self.failed.remove(&id) {
+            return LoadStatus::Error(e);
+        }
+        match self.cached(&id).await {
+            Some(texture) => LoadStatus::Loaded(texture),
+            None => LoadStatus::NotLoading,
+        }
+    }
+    /// Whether `completed` is of the load of its texture that is in flight.
+    fn is_current(&self, completed: &Completed) -> bool {
+        matches!(self.loading.get(&completed.id), Some((_, load)) if *load == completed.load)
+    }
+    /// Caches the textures whose load completed and counts them against the budget.
+    fn cache_completed(&mut self) {
+        let completed = std::mem::take(&mut *self.completed.lock().unwrap());
+        for completed in completed {
+            if !self.is_current(&completed) {
+                continue;
+            }
+            let Completed {
+                id,
+                duration,
+                result,
+                ..
+            } = completed;
+            self.loading.remove(&id);
+            match result {
+                Ok(t) => {
+                    if let Some(image) = t.image_data() {
+                        self.images.insert(id.clone(), image.clone());
+                    }
+                    self.textures
+                        .insert(&id, t.texture().clone(), t.gpu_size(), duration);
+                }
+                Err(e) => {
+                    self.failed.insert(id, e);
+                }
+            }
+        }
+        self.forget_evicted();
+    }
+    /// The cached texture with its `ImageData` if that was kept.
+    async fn cached(&mut self, id: &Identifier) -> Option<Arc<LoadedTexture>> {
+        match self.textures.get(id).await {
+            LoadStatus::Loaded(texture) => {
+                let image = self.images.get(id).cloned();
+                let size = self.textures.cached_size(id).unwrap_or_default();
+                Some(Arc::new(LoadedTexture::new(texture, image, size)))
+            }
+            _ => None,
+        }
+    }
+    /// Drops the `ImageData` of textures evicted from the cache.
+    fn forget_evicted(&mut self) {
+        let textures = &self.textures;
+        self.images
+            .retain(|id, _| textures.cached_size(id).is_some());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncTextureManager;
+    use crate::{LoadStatus, MemorySource};
+    use futures::executor::ThreadPoolBuilder;
+    use std::{fs, path::PathBuf, sync::Arc};
+    #[test]
+    fn manager() {
+        async_std::task::block_on(async {
+            let (needed_features, unsafe_features) =
+                (wgpu::Features::empty(), wgpu::UnsafeFeatures::disallow());
+
+            let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+            let adapter = instance
+                .request_adapter(
+                    &wgpu::RequestAdapterOptions {
+                        power_preference: wgpu::PowerPreference::Default,
+                        compatible_surface: None,
+                    },
+                    unsafe_features,
+                )
+                .await
+                .unwrap();
+
+            let adapter_features = adapter.features();
+            let (device, queue) = adapter
+                .request_device(
+                    &wgpu::DeviceDescriptor {
+                        features: adapter_features & needed_features,
+                        limits: wgpu::Limits::default(),
+                        shader_validation: true,
+                    },
+                    None,
+                )
+                .await
+                .unwrap();
+            let arc_device = Arc::new(device);
+            let arc_queue = Arc::new(queue);
+
+            let pool = Arc::new(ThreadPoolBuilder::new().create().unwrap());
+            let path = PathBuf::new().join("small_scream.png");
+            let mut texmngr =
+                AsyncTextureManager::new(pool.clone(), arc_device.clone(), arc_queue.clone());
+            texmngr.set_keep_image_data(false);
+
+            let texture = texmngr.load_texture(&path).await.await.unwrap();
+            assert!(texture.image_data().is_none());
+            // Counted once the load completes, before the manager is asked for it.
+            assert_eq!(texmngr.usage(), texture.gpu_size());
+            match texmngr.get(&path).await {
+                LoadStatus::Loaded(t) => assert!(Arc::ptr_eq(t.texture(), texture.texture())),
+                _ => panic!(),
+            }
+
+            let source =
+                MemorySource::new().with_file("textures/scream.png", fs::read(&path).unwrap());
+            let mut texmngr =
+                AsyncTextureManager::with_source(pool, arc_device, arc_queue, Arc::new(source));
+            let texture = texmngr
+                .load_texture("textures/scream.png")
+                .await
+                .await
+                .unwrap();
+            assert!(texture.image_data().is_some());
+            assert_eq!(texmngr.usage(), texture.gpu_size());
+        });
+    }
+}
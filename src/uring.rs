@@ -0,0 +1,317 @@
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use io_uring::{opcode, types, IoUring, Probe};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{Error, ErrorKind},
+    ops::Range,
+    os::unix::io::AsRawFd,
+    path::Path,
+    sync::{Arc, OnceLock},
+};
+
+/// Reads in flight at once on the shared ring, later ones wait for a free entry.
+const RING_ENTRIES: u32 = 256;
+
+/// Files are read whole into memory by the ring, larger ones are streamed on the pool.
+pub(crate) const MAX_RING_READ: u64 = 64 << 20;
+
+/// Called on the ring thread with the bytes read.
+pub(crate) type Reply = Box<dyn FnOnce(Result<Vec<u8>, Error>) + Send>;
+
+struct Request {
+    file: File,
+    offset: u64,
+    len: u64,
+    reply: Reply,
+}
+
+/// A read the kernel may be writing to, its file and buffer stay put until it completes.
+struct Read {
+    file: File,
+    buf: Vec<u8>,
+    filled: usize,
+    offset: u64,
+    reply: Reply,
+}
+
+/// Reads local files with io_uring on a background thread. Reads requested while the ring
+/// is busy are submitted together in one batch. See `FileLoadFuture::with_io_uring`.
+pub struct UringReader {
+    requests: Sender<Request>,
+}
+
+impl UringReader {
+    /// Starts a ring of `entries` reads, fails if the kernel lacks io_uring or its read.
+    pub fn new(entries: u32) -> Result<Self, Error> {
+        let ring = IoUring::new(entries)?;
+        let mut probe = Probe::new();
+        ring.submitter().register_probe(&mut probe)?;
+        if !probe.is_supported(opcode::Read::CODE) {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "io_uring can not read files",
+            ));
+        }
+        let (requests, received) = unbounded();
+        std::thread::Builder::new()
+            .name("io_uring".to_owned())
+            .spawn(move || run(ring, received, entries as usize))?;
+        Ok(Self { requests })
+    }
+    /// The ring shared by all loads, started on first use. `None` if io_uring is unavailable.
+    pub fn shared() -> Option<Arc<UringReader>> {
+        static SHARED: OnceLock<Option<Arc<UringReader>>> = OnceLock::new();
+        SHARED
+            .get_or_init(|| UringReader::new(RING_ENTRIES).ok().map(Arc::new))
+            .clone()
+    }
+    /// Reads `len` bytes of the file at `offset`, fewer if it ends before, see `open`.
+    /// Hands `reply` back if the ring thread stopped after an error.
+    pub(crate) fn read(
+        &self,
+        file: File,
+        offset: u64,
+        len: u64,
+        reply: Reply,
+    ) -> Result<(), Reply> {
+        self.requests
+            .send(Request {
+                file,
+                offset,
+                len,
+                reply,
+            })
+            .map_err(|e| e.0.reply)
+    }
+}
+
+/// Opens the file, returns it with the offset and length to read `range` of it, fewer bytes
+/// if it ends before, or all of it. Done before queueing so the ring thread does not block.
+pub(crate) fn open(path: &Path, range: Option<Range<u64>>) -> Result<(File, u64, u64), Error> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let (start, end) = match range {
+        Some(range) => {
+            let start = range.start.min(size);
+            (start, range.end.clamp(start, size))
+        }
+        None => (0, size),
+    };
+    Ok((file, start, end - start))
+}
+
+/// Queues the rest of `read` into the buffer behind what is filled. Fails if the submission
+/// queue is full, which only happens if more reads are in flight than the ring has entries.
+fn push(ring: &mut IoUring, slot: usize, read: &mut Read) -> Result<(), Error> {
+    let rest = &mut read.buf[read.filled..];
+    let entry = opcode::Read::new(
+        types::Fd(read.file.as_raw_fd()),
+        rest.as_mut_ptr(),
+        rest.len().min(u32::MAX as usize) as u32,
+    )
+    .offset((read.offset + read.filled as u64) as _)
+    .build()
+    .user_data(slot as u64);
+    // Safe as the file and buffer are kept in `reads` until the read completes.
+    unsafe { ring.submission().push(&entry) }
+        .map_err(|_| Error::new(ErrorKind::Other, "io_uring submission queue is full"))
+}
+
+/// Submits reads in batches of everything requested meanwhile and replies as they complete,
+/// until the `UringReader` is dropped.
+fn run(mut ring: IoUring, requests: Receiver<Request>, entries: usize) {
+    let mut reads: Vec<Option<Read>> = Vec::new();
+    let mut free = Vec::new();
+    let mut queued = VecDeque::new();
+    let mut in_flight = 0;
+    loop {
+        if in_flight == 0 && queued.is_empty() {
+            match requests.recv() {
+                Ok(request) => queued.push_back(request),
+                Err(_) => return,
+            }
+        }
+        queued.extend(requests.try_iter());
+        while in_flight < entries {
+            let Request {
+                file,
+                offset,
+                len,
+                reply,
+            } = match queued.pop_front() {
+                Some(request) => request,
+                None => break,
+            };
+            if len == 0 {
+                reply(Ok(Vec::new()));
+                continue;
+            }
+            let mut read = Read {
+                file,
+                buf: vec![0; len as usize],
+                filled: 0,
+                offset,
+                reply,
+            };
+            let slot = free.pop().unwrap_or_else(|| {
+                reads.push(None);
+                reads.len() - 1
+            });
+            if let Err(e) = push(&mut ring, slot, &mut read) {
+                free.push(slot);
+                (read.reply)(Err(e));
+                continue;
+            }
+            reads[slot] = Some(read);
+            in_flight += 1;
+        }
+        // Every request was answered without a read, e.g. as it was empty.
+        if in_flight == 0 {
+            continue;
+        }
+        match ring.submit_and_wait(1) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                for Read {
+                    file, buf, reply, ..
+                } in reads.drain(..).flatten()
+                {
+                    // The kernel may still read the file into the buffer, so both are leaked.
+                    std::mem::forget(file);
+                    std::mem::forget(buf);
+                    reply(Err(Error::new(e.kind(), e.to_string())));
+                }
+                for request in queued.drain(..) {
+                    (request.reply)(Err(Error::new(e.kind(), e.to_string())));
+                }
+                return;
+            }
+        }
+        let completed = ring
+            .completion()
+            .map(|entry| (entry.user_data() as usize, entry.result()))
+            .collect::<Vec<_>>();
+        for (slot, result) in completed {
+            in_flight -= 1;
+            let read = reads[slot]
+                .as_mut()
+                .expect("Completed reads are in flight!");
+            let done = match result {
+                0 => true,
+                read_bytes if read_bytes > 0 => {
+                    read.filled += read_bytes as usize;
+                    read.filled == read.buf.len()
+                }
+                error => {
+                    let e = Error::from_raw_os_error(-error);
+                    !matches!(e.kind(), ErrorKind::Interrupted | ErrorKind::WouldBlock)
+                }
+            };
+            if !done {
+                match push(&mut ring, slot, read) {
+                    Ok(()) => in_flight += 1,
+                    Err(e) => {
+                        let read = reads[slot].take().unwrap();
+                        free.push(slot);
+                        (read.reply)(Err(e));
+                    }
+                }
+                continue;
+            }
+            let mut read = reads[slot].take().unwrap();
+            free.push(slot);
+            if result < 0 {
+                (read.reply)(Err(Error::from_raw_os_error(-result)));
+            } else {
+                // The file got shorter if it ended early.
+                read.buf.truncate(read.filled);
+                (read.reply)(Ok(read.buf));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{open, UringReader};
+    use crate::FileLoadFuture;
+    use futures::executor::ThreadPoolBuilder;
+    use std::{
+        fs,
+        path::PathBuf,
+        sync::{mpsc::channel, Arc},
+        time::Duration,
+    };
+
+    #[test]
+    fn read() {
+        let ring = match UringReader::shared() {
+            Some(ring) => ring,
+            None => return,
+        };
+        let (tx, rx) = channel();
+        let path = PathBuf::from("benches/benchfiles/s01");
+        for range in &[None, Some(2..6), Some(300..400)] {
+            let tx = tx.clone();
+            let reply = Box::new(move |bytes| tx.send(bytes).unwrap());
+            let (file, offset, len) = open(&path, range.clone()).unwrap();
+            assert!(ring.read(file, offset, len, reply).is_ok());
+        }
+        let mut read = (0..3)
+            .map(|_| rx.recv().unwrap().unwrap())
+            .collect::<Vec<_>>();
+        let mut expected = vec![vec![], b"test".to_vec(), fs::read(&path).unwrap()];
+        read.sort();
+        expected.sort();
+        assert_eq!(read, expected);
+        assert!(open(&PathBuf::from("missing"), None).is_err());
+
+        // The ring does not wait for completions after a lone empty read, reads after it go on.
+        for range in &[Some(300..400), Some(2..6)] {
+            let tx = tx.clone();
+            let reply = Box::new(move |bytes| tx.send(bytes).unwrap());
+            let (file, offset, len) = open(&path, range.clone()).unwrap();
+            assert!(ring.read(file, offset, len, reply).is_ok());
+            rx.recv_timeout(Duration::from_secs(10)).unwrap().unwrap();
+        }
+    }
+
+    #[derive(Debug)]
+    struct LoadedFile {
+        bytes: Vec<u8>,
+    }
+
+    impl From<(PathBuf, Vec<u8>)> for LoadedFile {
+        fn from((_path, bytes): (PathBuf, Vec<u8>)) -> Self {
+            LoadedFile { bytes }
+        }
+    }
+
+    /// Loads the same files with io_uring, or on the pool where it is unavailable.
+    #[test]
+    fn load() {
+        let pool = Arc::new(ThreadPoolBuilder::new().create().unwrap());
+        let paths = (1..=16)
+            .map(|i| PathBuf::from(format!("benches/benchfiles/s{:02}", i)))
+            .collect::<Vec<_>>();
+        futures::executor::block_on(async {
+            let loads = paths.iter().map(|path| {
+                let f = FileLoadFuture::<LoadedFile>::new(path, pool.clone()).with_io_uring();
+                let progress = f.progress();
+                async move { (f.await.unwrap(), progress) }
+            });
+            for ((file, progress), path) in
+                futures::future::join_all(loads).await.iter().zip(&paths)
+            {
+                assert_eq!(file.bytes, fs::read(path).unwrap());
+                assert_eq!(progress.fraction(), Some(1.0));
+            }
+            let f = FileLoadFuture::<LoadedFile>::new(&paths[0], pool.clone())
+                .with_io_uring()
+                .with_range(2..6);
+            assert_eq!(f.await.unwrap().bytes, b"test");
+        });
+    }
+}
@@ -0,0 +1,272 @@
+use crate::{FormatError, Source};
+use ron::Value;
+use std::{
+    fmt,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+type Custom = Box<dyn Fn(&Value) -> Result<(), String> + Send + Sync>;
+
+enum Check {
+    Required,
+    Range(RangeInclusive<f64>),
+    FileExists,
+    Custom(Custom),
+}
+
+struct Rule {
+    field: String,
+    check: Check,
+}
+
+/// Checks run on a config file before it is decoded, registered per type with
+/// `ConfigManager::register_schema`. Fields are addressed by paths like `surface.roughness`,
+/// a number selects an element of a list, e.g. `layers.0.texture`.
+///
+/// Apart from `required`, checks of fields missing from the file are skipped.
+#[derive(Default)]
+pub struct Schema {
+    rules: Vec<Rule>,
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn required(self, field: &str) -> Self {
+        self.rule(field, Check::Required)
+    }
+    /// The field must be a number within `range`.
+    pub fn range(self, field: &str, range: RangeInclusive<f64>) -> Self {
+        self.rule(field, Check::Range(range))
+    }
+    /// The field must be a path to an existing file in the source of the manager.
+    pub fn file_exists(self, field: &str) -> Self {
+        self.rule(field, Check::FileExists)
+    }
+    /// `check` returns the problem with the value of the field, if any.
+    pub fn custom<F>(self, field: &str, check: F) -> Self
+    where
+        F: Fn(&Value) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.rule(field, Check::Custom(Box::new(check)))
+    }
+    fn rule(mut self, field: &str, check: Check) -> Self {
+        self.rules.push(Rule {
+            field: field.to_owned(),
+            check,
+        });
+        self
+    }
+    /// Every problem of `document`, located in its `text` if it is given. Paths are looked
+    /// up in `source`.
+    pub(crate) fn check(
+        &self,
+        document: &Value,
+        text: Option<&str>,
+        source: &dyn Source,
+    ) -> Vec<Problem> {
+        self.rules
+            .iter()
+            .filter_map(|rule| {
+                let message = match (lookup(document, &rule.field), &rule.check) {
+                    (None, Check::Required) => String::from("missing required field"),
+                    (None, _) | (Some(_), Check::Required) => return None,
+                    (Some(Value::Number(n)), Check::Range(range)) => {
+                        let n = n.into_f64();
+                        if range.contains(&n) {
+                            return None;
+                        }
+                        format!("{} is out of range {}..={}", n, range.start(), range.end())
+                    }
+                    (Some(_), Check::Range(_)) => String::from("expected a number"),
+                    (Some(Value::String(path)), Check::FileExists) => {
+                        if matches!(source.metadata(Path::new(path)), Ok(metadata) if !metadata.is_dir)
+                        {
+                            return None;
+                        }
+                        format!("file {} does not exist", path)
+                    }
+                    (Some(_), Check::FileExists) => String::from("expected a path"),
+                    (Some(value), Check::Custom(check)) => check(value).err()?,
+                };
+                Some(Problem {
+                    line: text.map_or(0, |text| locate(text, &rule.field)),
+                    field: rule.field.clone(),
+                    message,
+                })
+            })
+            .collect()
+    }
+}
+
+fn lookup<'a>(document: &'a Value, field: &str) -> Option<&'a Value> {
+    field
+        .split('.')
+        .try_fold(document, |value, segment| match value {
+            Value::Map(map) => map
+                .iter()
+                .find(|(key, _)| **key == Value::String(segment.to_owned()))
+                .map(|(_, value)| value),
+            Value::Seq(seq) => seq.get(segment.parse::<usize>().ok()?),
+            Value::Option(Some(value)) => lookup(value, segment),
+            _ => None,
+        })
+}
+
+/// Line of the field in the source text, found by searching its keys one after another.
+/// Falls back to the deepest parent found, a missing top level field is reported at the
+/// start of the document.
+fn locate(source: &str, field: &str) -> usize {
+    let mut offset = source.len() - source.trim_start().len();
+    for segment in field.split('.') {
+        if segment.parse::<usize>().is_ok() {
+            continue;
+        }
+        match find_key(&source[offset..], segment) {
+            Some(found) => offset += found,
+            None => break,
+        }
+    }
+    source[..offset].matches('\n').count() + 1
+}
+
+/// Offset of `key` written as a field name: `key:` in RON and YAML, `"key":` in JSON,
+/// `key =` or `[key]` in TOML.
+fn find_key(text: &str, key: &str) -> Option<usize> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    text.match_indices(key)
+        .map(|(index, _)| index)
+        .find(|&index| {
+            let before = text[..index].chars().next_back();
+            let after = text[index + key.len()..]
+                .trim_start_matches('"')
+                .trim_start();
+            match before {
+                Some(c) if is_ident(c) => false,
+                Some('[') | Some('.') if after.starts_with(']') || after.starts_with('.') => true,
+                _ => after.starts_with(':') || after.starts_with('='),
+            }
+        })
+}
+
+/// One problem of a config file, `line` is 0 if it is unknown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub line: usize,
+    pub field: String,
+    pub message: String,
+}
+
+impl From<FormatError> for Problem {
+    fn from(e: FormatError) -> Self {
+        Self {
+            line: e.line,
+            field: String::new(),
+            message: e.message,
+        }
+    }
+}
+
+/// All problems found while validating and decoding a config file.
+/// Returned as the inner error of the `InvalidData` load error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub path: PathBuf,
+    pub problems: Vec<Problem>,
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} problem(s) in {}",
+            self.problems.len(),
+            self.path.display()
+        )?;
+        for problem in &self.problems {
+            write!(f, "\n{}", self.path.display())?;
+            if problem.line != 0 {
+                write!(f, ":{}", problem.line)?;
+            }
+            if !problem.field.is_empty() {
+                write!(f, ": {}", problem.field)?;
+            }
+            write!(f, ": {}", problem.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationReport {}
+
+#[cfg(test)]
+mod tests {
+    use super::{locate, Schema};
+    use crate::{FileSystem, Format, MemorySource};
+    use ron::Value;
+
+    #[test]
+    fn locating() {
+        let ron = "\n(\n    name: \"stone\",\n    surface: (\n        name: \"top\",\n    ),\n)";
+        assert_eq!(locate(ron, "name"), 3);
+        assert_eq!(locate(ron, "surface.name"), 5);
+        assert_eq!(locate(ron, "missing"), 2);
+        let json = "{\n  \"layers\": [\n    { \"texture\": \"a.png\" }\n  ]\n}";
+        assert_eq!(locate(json, "layers.0.texture"), 3);
+        let toml = "name = \"stone\"\n\n[surface]\nroughness = 0.5\n";
+        assert_eq!(locate(toml, "surface.roughness"), 4);
+    }
+
+    #[test]
+    fn checks() {
+        let source =
+            "(\n    name: \"stone\",\n    roughness: 2.0,\n    albedo: \"missing.png\",\n)";
+        let document = Format::Ron.decode::<Value>(source.as_bytes()).unwrap();
+        let schema = Schema::new()
+            .required("name")
+            .required("metallic")
+            .range("roughness", 0.0..=1.0)
+            .range("metallic", 0.0..=1.0)
+            .file_exists("albedo")
+            .custom("name", |name| match name {
+                Value::String(name) if name == "stone" => Err(String::from("too generic")),
+                _ => Ok(()),
+            });
+        let problems = schema
+            .check(&document, Some(source), &FileSystem::default())
+            .into_iter()
+            .map(|problem| (problem.line, problem.field))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            vec![
+                (1, String::from("metallic")),
+                (3, String::from("roughness")),
+                (4, String::from("albedo")),
+                (2, String::from("name")),
+            ]
+        );
+    }
+    #[test]
+    fn files_in_source() {
+        let text = "(\n    albedo: \"textures/stone.png\",\n    normal: \"textures\",\n)";
+        let document = Format::Ron.decode::<Value>(text.as_bytes()).unwrap();
+        let schema = Schema::new().file_exists("albedo").file_exists("normal");
+        // Neither exists on the local disk, the source has the file and the directory.
+        let source = MemorySource::new().with_file("textures/stone.png", &b"png"[..]);
+        let problems = schema
+            .check(&document, Some(text), &source)
+            .into_iter()
+            .map(|problem| (problem.field, problem.message))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            vec![(
+                String::from("normal"),
+                String::from("file textures does not exist")
+            )]
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use crate::{FileBytes, Metadata, OpenFile, ReadMode, Source, Watch};
+use std::{
+    collections::BTreeSet,
+    io::{Error, ErrorKind},
+    ops::Range,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+/// A source mounted into a `Vfs`.
+struct Layer {
+    name: String,
+    prefix: PathBuf,
+    priority: i32,
+    source: Arc<dyn Source>,
+}
+
+/// Sources mounted at virtual path prefixes, e.g. the base game, DLC archives and mods.
+///
+/// A path is looked up in every layer mounted at a prefix of it, the file is taken from the
+/// layer with the highest priority that has it. Of layers with equal priority the one mounted
+/// last wins. Directories list the entries of all layers.
+#[derive(Default)]
+pub struct Vfs {
+    layers: Vec<Layer>,
+}
+
+/// `path` without `.` components, so prefixes compare equal however they are written.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+impl Vfs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Mounts `source` at `prefix` as the layer `name`, `source` sees paths relative to `prefix`.
+    pub fn mount<P: AsRef<Path>>(
+        mut self,
+        name: &str,
+        prefix: P,
+        priority: i32,
+        source: Arc<dyn Source>,
+    ) -> Self {
+        let index = self
+            .layers
+            .iter()
+            .position(|layer| layer.priority <= priority)
+            .unwrap_or(self.layers.len());
+        self.layers.insert(
+            index,
+            Layer {
+                name: name.to_owned(),
+                prefix: normalize(prefix.as_ref()),
+                priority,
+                source,
+            },
+        );
+        self
+    }
+    /// Names of the mounted layers, highest priority first.
+    pub fn layers(&self) -> impl Iterator<Item = &str> {
+        self.layers.iter().map(|layer| layer.name.as_str())
+    }
+    /// The layer a read of `path` is served by, if any has the file.
+    pub fn resolve<P: AsRef<Path>>(&self, path: P) -> Option<&str> {
+        self.find(path.as_ref(), |source, path| source.metadata(path))
+            .ok()
+            .map(|(_, layer)| layer.name.as_str())
+    }
+    /// Calls `f` with the source and relative path of every layer that may have `path`,
+    /// by priority, until one has it.
+    fn find<R>(
+        &self,
+        path: &Path,
+        f: impl Fn(&dyn Source, &Path) -> Result<R, Error>,
+    ) -> Result<(R, &Layer), Error> {
+        let path = normalize(path);
+        for layer in &self.layers {
+            if let Ok(relative) = path.strip_prefix(&layer.prefix) {
+                match f(&*layer.source, relative) {
+                    Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                    result => return result.map(|r| (r, layer)),
+                }
+            }
+        }
+        Err(Error::new(
+            ErrorKind::NotFound,
+            format!("{} not found in any layer", path.display()),
+        ))
+    }
+    /// Whether `path` is a parent of a mount point, those exist even if no layer has them.
+    fn is_mount_parent(&self, path: &Path) -> bool {
+        let path = normalize(path);
+        self.layers
+            .iter()
+            .any(|layer| layer.prefix != path && layer.prefix.starts_with(&path))
+    }
+}
+
+impl Source for Vfs {
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        self.read_layer(path).map(|(bytes, _)| bytes)
+    }
+    fn read_layer(&self, path: &Path) -> Result<(Vec<u8>, Option<String>), Error> {
+        self.find(path, |source, path| source.read(path))
+            .map(|(bytes, layer)| (bytes, Some(layer.name.clone())))
+    }
+    fn read_with(&self, path: &Path, mode: ReadMode) -> Result<(FileBytes, Option<String>), Error> {
+        self.find(path, |source, path| source.read_with(path, mode))
+            .map(|((bytes, _), layer)| (bytes, Some(layer.name.clone())))
+    }
+    fn open(&self, path: &Path) -> Result<OpenFile, Error> {
+        self.find(path, |source, path| source.open(path))
+            .map(|(file, layer)| OpenFile {
+                layer: Some(layer.name.clone()),
+                ..file
+            })
+    }
+    fn read_range(&self, path: &Path, range: Range<u64>) -> Result<Vec<u8>, Error> {
+        self.read_range_with(path, range, ReadMode::READ)
+            .map(|(bytes, _)| bytes.into_vec())
+    }
+    fn read_range_with(
+        &self,
+        path: &Path,
+        range: Range<u64>,
+        mode: ReadMode,
+    ) -> Result<(FileBytes, Option<String>), Error> {
+        self.find(path, |source, path| {
+            source.read_range_with(path, range.clone(), mode)
+        })
+        .map(|((bytes, _), layer)| (bytes, Some(layer.name.clone())))
+    }
+    fn metadata(&self, path: &Path) -> Result<Metadata, Error> {
+        match self.find(path, |source, path| source.metadata(path)) {
+            Err(e) if e.kind() == ErrorKind::NotFound && self.is_mount_parent(path) => {
+                Ok(Metadata {
+                    len: 0,
+                    modified: None,
+                    is_dir: true,
+                })
+            }
+            result => result.map(|(metadata, _)| metadata),
+        }
+    }
+    fn local_path(&self, path: &Path) -> Option<PathBuf> {
+        self.find(path, |source, path| {
+            source.metadata(path).map(|_| source.local_path(path))
+        })
+        .ok()?
+        .0
+    }
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+        let directory = normalize(path);
+        let mut found = self.is_mount_parent(path);
+        let mut entries = BTreeSet::new();
+        for layer in &self.layers {
+            if let Ok(relative) = directory.strip_prefix(&layer.prefix) {
+                match layer.source.read_dir(relative) {
+                    Ok(listed) => {
+                        found = true;
+                        entries.extend(
+                            listed
+                                .into_iter()
+                                .filter_map(|entry| entry.file_name().map(|name| path.join(name))),
+                        );
+                    }
+                    Err(e) if e.kind() == ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+            } else if let Ok(relative) = layer.prefix.strip_prefix(&directory) {
+                if let Some(Component::Normal(name)) = relative.components().next() {
+                    entries.insert(path.join(name));
+                }
+            }
+        }
+        if !found {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("{} not found in any layer", path.display()),
+            ));
+        }
+        Ok(entries.into_iter().collect())
+    }
+    /// Watches the file in the layer it is currently served by.
+    fn watch(&self, path: &Path, on_change: Box<dyn Fn(&Path) + Send>) -> Result<Watch, Error> {
+        let (_, layer) = self.find(path, |source, path| source.metadata(path))?;
+        let relative = normalize(path)
+            .strip_prefix(&layer.prefix)
+            .expect("Layers are only found below their prefix!")
+            .to_owned();
+        let path = path.to_owned();
+        layer
+            .source
+            .watch(&relative, Box::new(move |_| on_change(&path)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Vfs;
+    use crate::{AsyncFileManager, FileSystem, LoadStatus, Source, ZipSource};
+    use futures::executor::ThreadPool;
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        sync::Arc,
+    };
+
+    fn vfs() -> Vfs {
+        Vfs::new()
+            .mount("base", "", 0, Arc::new(FileSystem::new("testfiles")))
+            .mount(
+                "configs",
+                "configs",
+                0,
+                Arc::new(FileSystem::new("testfiles")),
+            )
+            .mount(
+                "dlc",
+                "",
+                10,
+                Arc::new(ZipSource::open("testfiles/assets.zip").unwrap()),
+            )
+    }
+
+    #[test]
+    fn layers() {
+        let vfs = vfs();
+        assert_eq!(
+            vfs.layers().collect::<Vec<_>>(),
+            vec!["dlc", "configs", "base"]
+        );
+        assert_eq!(vfs.resolve("configs/base.ron"), Some("dlc"));
+        assert_eq!(vfs.resolve("configs/derived.ron"), Some("configs"));
+        assert_eq!(vfs.resolve("./derived.ron"), Some("base"));
+        assert_eq!(
+            vfs.local_path(Path::new("configs/derived.ron")),
+            Some(PathBuf::from("testfiles/derived.ron"))
+        );
+        assert_eq!(vfs.local_path(Path::new("configs/base.ron")), None);
+        assert_eq!(vfs.resolve("configs/missing.ron"), None);
+        assert_eq!(
+            vfs.read(Path::new("textures/readme.txt")).unwrap(),
+            b"stored, not compressed\n"
+        );
+        let listed = vfs.read_dir(Path::new("configs")).unwrap();
+        assert!(listed.contains(&PathBuf::from("configs/base.ron")));
+        assert!(listed.contains(&PathBuf::from("configs/derived.ron")));
+        let listed = vfs.read_dir(Path::new("")).unwrap();
+        assert!(listed.contains(&PathBuf::from("configs")));
+        assert!(listed.contains(&PathBuf::from("textures")));
+        assert!(vfs.metadata(Path::new("configs")).unwrap().is_dir);
+    }
+
+    #[derive(Debug)]
+    struct LoadedFile {
+        bytes: Vec<u8>,
+    }
+
+    impl From<(PathBuf, Vec<u8>)> for LoadedFile {
+        fn from((_path, bytes): (PathBuf, Vec<u8>)) -> Self {
+            LoadedFile { bytes }
+        }
+    }
+
+    #[test]
+    fn manager() {
+        let pool = Arc::new(ThreadPool::new().unwrap());
+        let mut manager = AsyncFileManager::<LoadedFile>::with_source(pool, Arc::new(vfs()));
+        futures::executor::block_on(async {
+            for (path, layer) in &[("configs/base.ron", "dlc"), ("derived.ron", "base")] {
+                manager.load(path).await;
+                let file = match manager.get(path).await {
+                    LoadStatus::Loaded(file) => file,
+                    LoadStatus::Loading(f) => f.await.unwrap(),
+                    _ => panic!(),
+                };
+                assert_eq!(
+                    file.bytes,
+                    fs::read(Path::new("testfiles").join(Path::new(path).file_name().unwrap()))
+                        .unwrap()
+                );
+                assert_eq!(manager.layer(path).as_deref(), Some(*layer));
+
+                manager.load_range(path, 0..4).await;
+                if let LoadStatus::Loading(f) = manager.get_range(path, 0..4).await {
+                    f.await.unwrap();
+                }
+                let stats = manager.range_stats(path, 0..4).unwrap();
+                assert_eq!(stats.layer.as_deref(), Some(*layer));
+            }
+        });
+    }
+}
@@ -0,0 +1,267 @@
+use crate::{source::ConcurrentFile, Metadata, Source};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::File,
+    io::{Error, ErrorKind, Read, Seek, SeekFrom},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+use zip::ZipArchive;
+
+/// Reads the shared archive file at its own position, so clones never disturb each other.
+#[derive(Clone)]
+struct SharedFile {
+    file: Arc<ConcurrentFile>,
+    len: u64,
+    position: u64,
+}
+
+impl Read for SharedFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let read = self.file.read_at(buf, self.position)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for SharedFile {
+    fn seek(&mut self, position: SeekFrom) -> Result<u64, Error> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => checked_add(self.len, offset),
+            SeekFrom::Current(offset) => checked_add(self.position, offset),
+        };
+        self.position = position.ok_or_else(|| {
+            Error::new(ErrorKind::InvalidInput, "seek before the start of the file")
+        })?;
+        Ok(self.position)
+    }
+}
+
+fn checked_add(base: u64, offset: i64) -> Option<u64> {
+    if offset < 0 {
+        base.checked_sub(offset.unsigned_abs())
+    } else {
+        base.checked_add(offset as u64)
+    }
+}
+
+/// Files in a zip archive, addressed by their path inside it, e.g. `textures/rock.png`.
+///
+/// The archive is opened and indexed once. Entries are decompressed on the worker pool,
+/// concurrent reads each use a copy of the index that is kept for later reads.
+pub struct ZipSource {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    /// Name in the archive of every file.
+    files: HashMap<PathBuf, String>,
+    /// Every directory, including those only implied by the files in them.
+    directories: BTreeSet<PathBuf>,
+    idle: Mutex<Vec<ZipArchive<SharedFile>>>,
+}
+
+impl ZipSource {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = File::open(path.as_ref())?;
+        let metadata = file.metadata()?;
+        let archive = ZipArchive::new(SharedFile {
+            file: Arc::new(ConcurrentFile::new(file)),
+            len: metadata.len(),
+            position: 0,
+        })?;
+        let mut files = HashMap::new();
+        let mut directories = BTreeSet::new();
+        directories.insert(PathBuf::new());
+        for name in archive.file_names() {
+            let entry = PathBuf::from(name);
+            let mut parent = entry.parent();
+            while let Some(directory) = parent {
+                directories.insert(directory.to_owned());
+                parent = directory.parent();
+            }
+            if name.ends_with('/') {
+                directories.insert(entry);
+            } else {
+                files.insert(entry, name.to_owned());
+            }
+        }
+        Ok(Self {
+            path: path.as_ref().to_owned(),
+            modified: metadata.modified().ok(),
+            files,
+            directories,
+            idle: Mutex::new(vec![archive]),
+        })
+    }
+    /// Path of the archive on disk.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    fn name(&self, path: &Path) -> Result<&str, Error> {
+        self.files
+            .get(&normalize(path))
+            .map(String::as_str)
+            .ok_or_else(|| self.not_found(path))
+    }
+    fn not_found(&self, path: &Path) -> Error {
+        Error::new(
+            ErrorKind::NotFound,
+            format!("{} not found in {}", path.display(), self.path.display()),
+        )
+    }
+    /// Runs `f` on an idle copy of the archive. The last idle copy is kept for the next read
+    /// and cloned instead, so copies are only made while reads overlap.
+    fn with_archive<R>(&self, f: impl FnOnce(&mut ZipArchive<SharedFile>) -> R) -> R {
+        let mut archive = {
+            let mut idle = self.idle.lock().unwrap();
+            if idle.len() == 1 {
+                idle[0].clone()
+            } else {
+                idle.pop().expect("The last archive is never taken!")
+            }
+        };
+        let result = f(&mut archive);
+        self.idle.lock().unwrap().push(archive);
+        result
+    }
+}
+
+/// `path` without `.` components, the way it is written in the archive.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+impl Source for ZipSource {
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        let name = self.name(path)?;
+        self.with_archive(|archive| {
+            let mut entry = archive.by_name(name)?;
+            let mut bytes = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut bytes)?;
+            Ok(bytes)
+        })
+    }
+    fn metadata(&self, path: &Path) -> Result<Metadata, Error> {
+        let normalized = normalize(path);
+        if self.directories.contains(&normalized) {
+            return Ok(Metadata {
+                len: 0,
+                modified: self.modified,
+                is_dir: true,
+            });
+        }
+        let name = self.name(path)?;
+        let len = self.with_archive(|archive| archive.by_name(name).map(|entry| entry.size()))?;
+        Ok(Metadata {
+            len,
+            modified: self.modified,
+            is_dir: false,
+        })
+    }
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+        let directory = normalize(path);
+        if !self.directories.contains(&directory) {
+            return Err(self.not_found(path));
+        }
+        let mut entries = self
+            .files
+            .keys()
+            .chain(self.directories.iter())
+            .filter(|entry| entry.parent() == Some(&directory))
+            .map(|entry| path.join(entry.file_name().unwrap()))
+            .collect::<Vec<_>>();
+        entries.sort();
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ZipSource;
+    use crate::{AsyncFileManager, LoadStatus, Source};
+    use futures::executor::ThreadPool;
+    use std::{
+        convert::TryFrom,
+        fs,
+        path::{Path, PathBuf},
+        sync::Arc,
+    };
+
+    #[test]
+    fn read() {
+        let source = ZipSource::open("testfiles/assets.zip").unwrap();
+        assert_eq!(
+            source.read(Path::new("material.ron")).unwrap(),
+            fs::read("testfiles/material.ron").unwrap()
+        );
+        assert_eq!(
+            source.read(Path::new("./textures/readme.txt")).unwrap(),
+            b"stored, not compressed\n"
+        );
+        assert_eq!(
+            source
+                .read_range(Path::new("configs/base.ron"), 0..1)
+                .unwrap(),
+            b"("
+        );
+        assert_eq!(
+            source.metadata(Path::new("configs/base.ron")).unwrap().len,
+            fs::metadata("testfiles/base.ron").unwrap().len()
+        );
+        assert!(source.metadata(Path::new("configs")).unwrap().is_dir);
+        assert_eq!(
+            source.read_dir(Path::new("")).unwrap(),
+            vec![
+                PathBuf::from("configs"),
+                PathBuf::from("material.ron"),
+                PathBuf::from("textures"),
+            ]
+        );
+        assert_eq!(
+            source.read(Path::new("missing.ron")).unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+
+    #[derive(Debug)]
+    struct LoadedFile {
+        string: String,
+    }
+
+    impl TryFrom<(PathBuf, Vec<u8>)> for LoadedFile {
+        type Error = std::string::FromUtf8Error;
+        fn try_from((_path, bytes): (PathBuf, Vec<u8>)) -> Result<Self, Self::Error> {
+            Ok(LoadedFile {
+                string: String::from_utf8(bytes)?,
+            })
+        }
+    }
+
+    #[test]
+    fn manager() {
+        let pool = Arc::new(ThreadPool::new().unwrap());
+        let source = Arc::new(ZipSource::open("testfiles/assets.zip").unwrap());
+        let mut manager = AsyncFileManager::<LoadedFile>::with_source(pool, source);
+        let paths = ["material.ron", "configs/base.ron", "textures/readme.txt"];
+        futures::executor::block_on(async {
+            // Concurrent reads from the same archive.
+            for _ in 0..4 {
+                for path in paths.iter() {
+                    manager.evict(path);
+                    manager.load(path).await;
+                }
+                for path in paths.iter() {
+                    let file = match manager.get(path).await {
+                        LoadStatus::Loaded(file) => file,
+                        LoadStatus::Loading(f) => f.await.unwrap(),
+                        _ => panic!(),
+                    };
+                    assert!(!file.string.is_empty());
+                }
+            }
+        });
+    }
+}